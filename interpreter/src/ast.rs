@@ -1,7 +1,7 @@
 use std::rc::Rc;
 
 use crate::{
-    token::{LiteralValue, Token},
+    token::{LiteralValue, Span, Token},
     utils::Late,
 };
 
@@ -15,6 +15,12 @@ pub enum Stmt {
     If(IfStmt),
     While(WhileStmt),
     Return(ReturnStmt),
+    Break(BreakStmt),
+    Continue(ContinueStmt),
+    Throw(ThrowStmt),
+    Try(TryStmt),
+    Import(ImportStmt),
+    Export(ExportStmt),
 }
 
 impl Stmt {
@@ -36,6 +42,12 @@ pub trait StmtVisitor<T> {
     fn visit_if_stmt(&mut self, stmt: &IfStmt, ptr: &Rc<Stmt>) -> T;
     fn visit_while_stmt(&mut self, stmt: &WhileStmt, ptr: &Rc<Stmt>) -> T;
     fn visit_return_stmt(&mut self, stmt: &ReturnStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_break_stmt(&mut self, stmt: &BreakStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_continue_stmt(&mut self, stmt: &ContinueStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_try_stmt(&mut self, stmt: &TryStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt, ptr: &Rc<Stmt>) -> T;
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt, ptr: &Rc<Stmt>) -> T;
 }
 
 pub trait VisitStmt {
@@ -55,6 +67,12 @@ impl VisitStmt for Rc<Stmt> {
             If(expr) => visitor.visit_if_stmt(expr, self),
             While(expr) => visitor.visit_while_stmt(expr, self),
             Return(expr) => visitor.visit_return_stmt(expr, self),
+            Break(expr) => visitor.visit_break_stmt(expr, self),
+            Continue(expr) => visitor.visit_continue_stmt(expr, self),
+            Throw(expr) => visitor.visit_throw_stmt(expr, self),
+            Try(expr) => visitor.visit_try_stmt(expr, self),
+            Import(expr) => visitor.visit_import_stmt(expr, self),
+            Export(expr) => visitor.visit_export_stmt(expr, self),
         }
     }
 }
@@ -97,6 +115,9 @@ pub struct IfStmt {
 pub struct WhileStmt {
     pub condition: Rc<Expr>,
     pub body: Rc<Stmt>,
+    /// Expression which is evaluated after each iteration, including ones
+    /// which are ended by `continue`. Only loops desugared from `for` have one.
+    pub increment: Option<Rc<Expr>>,
 }
 
 pub struct ReturnStmt {
@@ -104,6 +125,59 @@ pub struct ReturnStmt {
     pub value: Option<Rc<Expr>>,
 }
 
+pub struct BreakStmt {
+    pub token: Token,
+}
+
+pub struct ContinueStmt {
+    pub token: Token,
+}
+
+pub struct ThrowStmt {
+    pub token: Token,
+    pub value: Rc<Expr>,
+}
+
+pub struct TryStmt {
+    pub body: Vec<Rc<Stmt>>,
+    pub catch: Option<CatchClause>,
+    pub finally: Option<Vec<Rc<Stmt>>>,
+}
+
+pub struct CatchClause {
+    /// The variable the caught value is bound to.
+    pub name: Token,
+    pub body: Vec<Rc<Stmt>>,
+}
+
+pub struct ImportStmt {
+    pub keyword: Token,
+    /// The variable the namespace of the module is bound to.
+    pub name: String,
+    /// The path of the module, relative to the importing file.
+    pub path: String,
+    /// The whole statement.
+    pub span: Span,
+}
+
+pub struct ExportStmt {
+    pub keyword: Token,
+    /// A variable, function or class declaration.
+    pub declaration: Rc<Stmt>,
+}
+
+impl ExportStmt {
+    /// Returns the name of the exported declaration.
+    pub fn name(&self) -> &Token {
+        match self.declaration.as_ref() {
+            Stmt::Var(stmt) => &stmt.name,
+            Stmt::Function(stmt) => &stmt.name,
+            Stmt::Class(stmt) => &stmt.name,
+            _ => panic!(),
+        }
+    }
+}
+
 pub enum Expr {
     Literal(LiteralExpr),
     Variable(VariableExpr),
@@ -117,6 +191,10 @@ pub enum Expr {
     Set(SetExpr),
     This(ThisExpr),
     Super(SuperExpr),
+    List(ListExpr),
+    Map(MapExpr),
+    Index(IndexExpr),
+    SetIndex(SetIndexExpr),
 }
 
 impl Expr {
@@ -126,6 +204,29 @@ impl Expr {
             _ => panic!(),
         }
     }
+
+    /// Returns the span of the source code of this expression.
+    pub fn span(&self) -> Span {
+        use Expr::*;
+        match self {
+            Literal(expr) => expr.span.clone(),
+            Variable(expr) => expr.name.span.clone(),
+            Assign(expr) => expr.name.span.to(&expr.value.span()),
+            Unary(expr) => expr.operator.span.to(&expr.expression.span()),
+            Binary(expr) => expr.left.span().to(&expr.right.span()),
+            Condition(expr) => expr.left.span().to(&expr.right.span()),
+            Grouping(expr) => expr.span.clone(),
+            Call(expr) => expr.callee.span().to(&expr.paren.span),
+            Get(expr) => expr.object.span().to(&expr.name.span),
+            Set(expr) => expr.object.span().to(&expr.value.span()),
+            This(expr) => expr.token.span.clone(),
+            Super(expr) => expr.keyword.span.to(&expr.method.span),
+            List(expr) => expr.span.clone(),
+            Map(expr) => expr.span.clone(),
+            Index(expr) => expr.object.span().to(&expr.bracket.span),
+            SetIndex(expr) => expr.object.span().to(&expr.value.span()),
+        }
+    }
 }
 
 pub trait VisitExpr {
@@ -145,6 +246,10 @@ pub trait ExprVisitor<T> {
     fn visit_set_expr(&mut self, expr: &SetExpr, ptr: &Rc<Expr>) -> T;
     fn visit_this_expr(&mut self, expr: &ThisExpr, ptr: &Rc<Expr>) -> T;
     fn visit_super_expr(&mut self, expr: &SuperExpr, ptr: &Rc<Expr>) -> T;
+    fn visit_list_expr(&mut self, expr: &ListExpr, ptr: &Rc<Expr>) -> T;
+    fn visit_map_expr(&mut self, expr: &MapExpr, ptr: &Rc<Expr>) -> T;
+    fn visit_index_expr(&mut self, expr: &IndexExpr, ptr: &Rc<Expr>) -> T;
+    fn visit_set_index_expr(&mut self, expr: &SetIndexExpr, ptr: &Rc<Expr>) -> T;
 }
 
 impl VisitExpr for Rc<Expr> {
@@ -163,12 +268,17 @@ impl VisitExpr for Rc<Expr> {
             Set(expr) => visitor.visit_set_expr(expr, self),
             This(expr) => visitor.visit_this_expr(expr, self),
             Super(expr) => visitor.visit_super_expr(expr, self),
+            List(expr) => visitor.visit_list_expr(expr, self),
+            Map(expr) => visitor.visit_map_expr(expr, self),
+            Index(expr) => visitor.visit_index_expr(expr, self),
+            SetIndex(expr) => visitor.visit_set_index_expr(expr, self),
         }
     }
 }
 
 pub struct LiteralExpr {
     pub value: LiteralValue,
+    pub span: Span,
 }
 
 pub struct VariableExpr {
@@ -201,6 +311,7 @@ pub struct ConditionExpr {
 
 pub struct GroupingExpr {
     pub expression: Rc<Expr>,
+    pub span: Span,
 }
 
 pub struct CallExpr {
@@ -230,3 +341,33 @@ pub struct SuperExpr {
     pub method: Token,
     pub scope_index: Late<Option<usize>>,
 }
+
+pub struct ListExpr {
+    pub elements: Vec<Rc<Expr>>,
+    pub span: Span,
+}
+
+/// The key and value of an entry in a map literal.
+pub type MapEntry = (Rc<Expr>, Rc<Expr>);
+
+pub struct MapExpr {
+    /// The opening brace.
+    pub brace: Token,
+    pub entries: Vec<MapEntry>,
+    pub span: Span,
+}
+
+pub struct IndexExpr {
+    pub object: Rc<Expr>,
+    /// The closing bracket.
+    pub bracket: Token,
+    pub index: Rc<Expr>,
+}
+
+pub struct SetIndexExpr {
+    pub object: Rc<Expr>,
+    /// The closing bracket.
+    pub bracket: Token,
+    pub index: Rc<Expr>,
+    pub value: Rc<Expr>,
+}
@@ -0,0 +1,117 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{
+        AssignExpr, BinaryExpr, CallExpr, ConditionExpr, Expr, ExprVisitor, GetExpr, GroupingExpr,
+        IndexExpr, ListExpr, LiteralExpr, MapExpr, SetExpr, SetIndexExpr, SuperExpr, ThisExpr,
+        UnaryExpr, VariableExpr, VisitExpr,
+    },
+    interpreter::RuntimeValue,
+    token::LiteralValue,
+};
+
+/// Renders expressions in a parenthesized prefix notation, which makes their
+/// structure explicit. For example, `-a * (b + 1)` is rendered as
+/// `(* (- a) (group (+ b 1)))`.
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn print(&mut self, expr: &Rc<Expr>) -> String {
+        expr.accept(self)
+    }
+
+    fn parenthesize<'a>(
+        &mut self,
+        name: &str,
+        exprs: impl IntoIterator<Item = &'a Rc<Expr>>,
+    ) -> String {
+        let mut result = format!("({}", name);
+        for expr in exprs {
+            result.push(' ');
+            result.push_str(&self.print(expr));
+        }
+        result.push(')');
+        result
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_literal_expr(&mut self, expr: &LiteralExpr, _: &Rc<Expr>) -> String {
+        match &expr.value {
+            LiteralValue::Nil => "nil".to_string(),
+            LiteralValue::Bool(value) => value.to_string(),
+            LiteralValue::Number(value) => RuntimeValue::Number(*value).to_string(),
+            LiteralValue::String(value) => format!("{:?}", value),
+        }
+    }
+
+    fn visit_variable_expr(&mut self, expr: &VariableExpr, _: &Rc<Expr>) -> String {
+        expr.name.lexeme.clone()
+    }
+
+    fn visit_assign_expr(&mut self, expr: &AssignExpr, _: &Rc<Expr>) -> String {
+        format!("(= {} {})", expr.name.lexeme, self.print(&expr.value))
+    }
+
+    fn visit_unary_expr(&mut self, expr: &UnaryExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize(&expr.operator.lexeme, [&expr.expression])
+    }
+
+    fn visit_binary_expr(&mut self, expr: &BinaryExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize(&expr.operator.lexeme, [&expr.left, &expr.right])
+    }
+
+    fn visit_condition_expr(&mut self, expr: &ConditionExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize(&expr.operator.lexeme, [&expr.left, &expr.right])
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &GroupingExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize("group", [&expr.expression])
+    }
+
+    fn visit_call_expr(&mut self, expr: &CallExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize("call", [&expr.callee].into_iter().chain(&expr.arguments))
+    }
+
+    fn visit_get_expr(&mut self, expr: &GetExpr, _: &Rc<Expr>) -> String {
+        format!("(get {} {})", self.print(&expr.object), expr.name.lexeme)
+    }
+
+    fn visit_set_expr(&mut self, expr: &SetExpr, _: &Rc<Expr>) -> String {
+        format!(
+            "(set {} {} {})",
+            self.print(&expr.object),
+            expr.name.lexeme,
+            self.print(&expr.value)
+        )
+    }
+
+    fn visit_this_expr(&mut self, _: &ThisExpr, _: &Rc<Expr>) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super_expr(&mut self, expr: &SuperExpr, _: &Rc<Expr>) -> String {
+        format!("(super {})", expr.method.lexeme)
+    }
+
+    fn visit_list_expr(&mut self, expr: &ListExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize("list", &expr.elements)
+    }
+
+    fn visit_map_expr(&mut self, expr: &MapExpr, _: &Rc<Expr>) -> String {
+        let entries: Vec<_> = expr
+            .entries
+            .iter()
+            .map(|(key, value)| format!(" ({} {})", self.print(key), self.print(value)))
+            .collect();
+        format!("(map{})", entries.concat())
+    }
+
+    fn visit_index_expr(&mut self, expr: &IndexExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize("index", [&expr.object, &expr.index])
+    }
+
+    fn visit_set_index_expr(&mut self, expr: &SetIndexExpr, _: &Rc<Expr>) -> String {
+        self.parenthesize("set-index", [&expr.object, &expr.index, &expr.value])
+    }
+}
@@ -1,6 +1,7 @@
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::{
+    gc::{self, Trace},
     interpreter::{EarlyReturn, RuntimeError, RuntimeValue},
     token::Token,
 };
@@ -29,6 +30,16 @@ impl Environment {
         self.values.insert(name.to_string(), value);
     }
 
+    /// Returns the values defined directly in this environment.
+    pub fn values(&self) -> impl Iterator<Item = (&String, &RuntimeValue)> {
+        self.values.iter()
+    }
+
+    /// Removes all values from this environment and returns them.
+    pub fn clear(&mut self) -> HashMap<String, RuntimeValue> {
+        std::mem::take(&mut self.values)
+    }
+
     pub fn assign_at(&mut self, name: &str, scope_index: usize, value: RuntimeValue) {
         self.with_scope_at(scope_index, |scope| {
             scope.insert(name.to_string(), value);
@@ -42,10 +53,10 @@ impl Environment {
         } else {
             match &mut self.enclosing {
                 Some(enclosing) => enclosing.borrow_mut().assign(name, value),
-                None => RuntimeError {
-                    message: format!("Cannot assign to undefined variable '{}'.", name.lexeme),
-                    token: name.clone(),
-                }
+                None => RuntimeError::new(
+                    format!("Cannot assign to undefined variable '{}'.", name.lexeme),
+                    name,
+                )
                 .into(),
             }
         }
@@ -57,14 +68,13 @@ impl Environment {
 
     pub fn get(&self, name: &Token) -> Result<RuntimeValue, EarlyReturn> {
         match self.values.get(&name.lexeme) {
-            Some(value) => return Ok(value.clone()),
+            Some(value) => Ok(value.clone()),
             None => match &self.enclosing {
                 Some(enclosing) => enclosing.borrow().get(name),
-                None => RuntimeError {
-                    message: format!("Variable '{}' is not defined.", name.lexeme),
-                    token: name.clone(),
+                None => {
+                    RuntimeError::new(format!("Variable '{}' is not defined.", name.lexeme), name)
+                        .into()
                 }
-                .into(),
             },
         }
     }
@@ -84,3 +94,14 @@ impl Environment {
             .with_scope_at(scope_index - 1, run)
     }
 }
+
+impl Trace for Environment {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(enclosing) = &self.enclosing {
+            visit(gc::address(enclosing));
+        }
+        for value in self.values.values() {
+            value.trace(visit);
+        }
+    }
+}
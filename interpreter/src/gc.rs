@@ -0,0 +1,331 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment,
+    interpreter::{
+        BuiltinFunction, Class, DeclaredFunction, Instance, List, Map, Module, RuntimeValue,
+    },
+};
+
+/// Number of objects which have to be allocated before the first collection.
+const INITIAL_COLLECTION_THRESHOLD: usize = 1024;
+const COLLECTION_GROW_FACTOR: usize = 2;
+
+/// Implemented by objects which can be part of a reference cycle.
+pub trait Trace {
+    /// Calls `visit` with the address of each object, which this object
+    /// directly references.
+    fn trace(&self, visit: &mut dyn FnMut(usize));
+}
+
+/// Cycle collector for the reference counted objects of the interpreter.
+///
+/// Environments, instances, lists, maps, functions, bound native methods,
+/// classes and modules are reference counted, but can reference each other in
+/// cycles, for example when a closure is stored in the environment it closes
+/// over. Reference counting alone never
+/// frees such cycles.
+///
+/// The heap keeps weak references to all of these objects and periodically
+/// finds the ones which are only kept alive by references from other tracked
+/// objects (trial deletion). Such objects are unreachable from the rest of the
+/// program. Their cycles are broken by clearing the variables of unreachable
+/// environments, the fields of unreachable instances and the elements of
+/// unreachable lists and maps, after which reference counting frees them.
+///
+/// Objects do not have to be registered as roots, since any reference which
+/// does not come from a tracked object, like the ones held by the interpreter
+/// or on the Rust stack, keeps an object alive.
+pub struct Heap {
+    objects: Vec<WeakObject>,
+    next_collection: usize,
+}
+
+enum WeakObject {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<Instance>>),
+    List(Weak<RefCell<List>>),
+    Map(Weak<RefCell<Map>>),
+    Function(Weak<DeclaredFunction>),
+    Native(Weak<BuiltinFunction>),
+    Class(Weak<Class>),
+    Module(Weak<Module>),
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            WeakObject::Environment(object) => object.upgrade().map(Object::Environment),
+            WeakObject::Instance(object) => object.upgrade().map(Object::Instance),
+            WeakObject::List(object) => object.upgrade().map(Object::List),
+            WeakObject::Map(object) => object.upgrade().map(Object::Map),
+            WeakObject::Function(object) => object.upgrade().map(Object::Function),
+            WeakObject::Native(object) => object.upgrade().map(Object::Native),
+            WeakObject::Class(object) => object.upgrade().map(Object::Class),
+            WeakObject::Module(object) => object.upgrade().map(Object::Module),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            WeakObject::Environment(object) => object.strong_count() > 0,
+            WeakObject::Instance(object) => object.strong_count() > 0,
+            WeakObject::List(object) => object.strong_count() > 0,
+            WeakObject::Map(object) => object.strong_count() > 0,
+            WeakObject::Function(object) => object.strong_count() > 0,
+            WeakObject::Native(object) => object.strong_count() > 0,
+            WeakObject::Class(object) => object.strong_count() > 0,
+            WeakObject::Module(object) => object.strong_count() > 0,
+        }
+    }
+}
+
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<Instance>>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+    Function(Rc<DeclaredFunction>),
+    Native(Rc<BuiltinFunction>),
+    Class(Rc<Class>),
+    Module(Rc<Module>),
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(object) => address(object),
+            Object::Instance(object) => address(object),
+            Object::List(object) => address(object),
+            Object::Map(object) => address(object),
+            Object::Function(object) => address(object),
+            Object::Native(object) => address(object),
+            Object::Class(object) => address(object),
+            Object::Module(object) => address(object),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(object) => Rc::strong_count(object),
+            Object::Instance(object) => Rc::strong_count(object),
+            Object::List(object) => Rc::strong_count(object),
+            Object::Map(object) => Rc::strong_count(object),
+            Object::Function(object) => Rc::strong_count(object),
+            Object::Native(object) => Rc::strong_count(object),
+            Object::Class(object) => Rc::strong_count(object),
+            Object::Module(object) => Rc::strong_count(object),
+        }
+    }
+
+    /// Traces the references of the object and returns whether that was
+    /// possible. Objects which are currently mutably borrowed cannot be
+    /// traced.
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        match self {
+            Object::Environment(object) => match object.try_borrow() {
+                Ok(object) => object.trace(visit),
+                Err(_) => return false,
+            },
+            Object::Instance(object) => match object.try_borrow() {
+                Ok(object) => object.trace(visit),
+                Err(_) => return false,
+            },
+            Object::List(object) => match object.try_borrow() {
+                Ok(object) => object.trace(visit),
+                Err(_) => return false,
+            },
+            Object::Map(object) => match object.try_borrow() {
+                Ok(object) => object.trace(visit),
+                Err(_) => return false,
+            },
+            Object::Function(object) => object.trace(visit),
+            Object::Native(object) => object.trace(visit),
+            Object::Class(object) => object.trace(visit),
+            Object::Module(object) => object.trace(visit),
+        }
+        true
+    }
+
+    /// Removes the values which the object holds on to and returns them, so
+    /// that they can be dropped after all objects have been cleared.
+    fn clear(&self) -> Vec<RuntimeValue> {
+        match self {
+            Object::Environment(object) => object.borrow_mut().clear().into_values().collect(),
+            Object::Instance(object) => object.borrow_mut().clear().into_values().collect(),
+            Object::List(object) => object.borrow_mut().clear(),
+            Object::Map(object) => object.borrow_mut().clear(),
+            // Functions, classes and modules are immutable, so every cycle
+            // they are part of also goes through an environment, instance,
+            // list or map.
+            Object::Function(_) | Object::Native(_) | Object::Class(_) | Object::Module(_) => {
+                vec![]
+            }
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: vec![],
+            next_collection: INITIAL_COLLECTION_THRESHOLD,
+        }
+    }
+
+    pub fn environment(&mut self, environment: Environment) -> Rc<RefCell<Environment>> {
+        let environment = Rc::new(RefCell::new(environment));
+        self.objects
+            .push(WeakObject::Environment(Rc::downgrade(&environment)));
+        environment
+    }
+
+    pub fn instance(&mut self, instance: Instance) -> Rc<RefCell<Instance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.objects
+            .push(WeakObject::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    pub fn list(&mut self, list: List) -> Rc<RefCell<List>> {
+        let list = Rc::new(RefCell::new(list));
+        self.objects.push(WeakObject::List(Rc::downgrade(&list)));
+        list
+    }
+
+    pub fn map(&mut self, map: Map) -> Rc<RefCell<Map>> {
+        let map = Rc::new(RefCell::new(map));
+        self.objects.push(WeakObject::Map(Rc::downgrade(&map)));
+        map
+    }
+
+    pub fn function(&mut self, function: DeclaredFunction) -> Rc<DeclaredFunction> {
+        let function = Rc::new(function);
+        self.objects
+            .push(WeakObject::Function(Rc::downgrade(&function)));
+        function
+    }
+
+    /// Tracks a native function, which has been bound to a receiver that can
+    /// be part of a cycle.
+    pub fn native(&mut self, function: BuiltinFunction) -> Rc<BuiltinFunction> {
+        let function = Rc::new(function);
+        self.objects
+            .push(WeakObject::Native(Rc::downgrade(&function)));
+        function
+    }
+
+    pub fn class(&mut self, class: Class) -> Rc<Class> {
+        let class = Rc::new(class);
+        self.objects.push(WeakObject::Class(Rc::downgrade(&class)));
+        class
+    }
+
+    pub fn module(&mut self, module: Module) -> Rc<Module> {
+        let module = Rc::new(module);
+        self.objects
+            .push(WeakObject::Module(Rc::downgrade(&module)));
+        module
+    }
+
+    /// Whether enough objects have been allocated since the last collection
+    /// to warrant another one.
+    pub fn should_collect(&self) -> bool {
+        self.objects.len() >= self.next_collection
+    }
+
+    /// Frees all objects which are only reachable through reference cycles and
+    /// returns how many objects have been freed.
+    pub fn collect(&mut self) -> usize {
+        let objects: Vec<Object> = self
+            .objects
+            .iter()
+            .filter_map(WeakObject::upgrade)
+            .collect();
+
+        let indices: HashMap<usize, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| (object.address(), index))
+            .collect();
+
+        // Start with the reference count of each object, without the reference
+        // created by upgrading it above, and subtract the references which
+        // come from other tracked objects. What remains are references from
+        // outside, which make an object reachable.
+        let mut external_counts: Vec<usize> = objects
+            .iter()
+            .map(|object| object.strong_count() - 1)
+            .collect();
+        let mut children: Vec<Vec<usize>> = vec![vec![]; objects.len()];
+        for (index, object) in objects.iter().enumerate() {
+            let traced = object.trace(&mut |address| {
+                if let Some(&child) = indices.get(&address) {
+                    external_counts[child] -= 1;
+                    children[index].push(child);
+                }
+            });
+
+            // The references of an object, which cannot be traced, are unknown,
+            // so it has to be treated as reachable.
+            if !traced {
+                external_counts[index] += 1;
+            }
+        }
+
+        let mut reachable = vec![false; objects.len()];
+        let mut gray: Vec<usize> = (0..objects.len())
+            .filter(|&index| external_counts[index] > 0)
+            .collect();
+        while let Some(index) = gray.pop() {
+            if reachable[index] {
+                continue;
+            }
+            reachable[index] = true;
+            gray.extend(children[index].iter().filter(|&&child| !reachable[child]));
+        }
+
+        let garbage: Vec<_> = objects
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !reachable[*index])
+            .map(|(_, object)| object.clear())
+            .collect();
+        let freed = objects.len() - reachable.iter().filter(|&&reachable| reachable).count();
+
+        // Dropping the cleared values and the upgraded references releases the
+        // unreachable objects.
+        drop(garbage);
+        drop(objects);
+
+        self.objects.retain(WeakObject::is_alive);
+        self.next_collection =
+            (self.objects.len() * COLLECTION_GROW_FACTOR).max(INITIAL_COLLECTION_THRESHOLD);
+
+        freed
+    }
+}
+
+impl Trace for RuntimeValue {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        match self {
+            RuntimeValue::BuiltinFunction(function) => visit(address(function)),
+            RuntimeValue::DeclaredFunction(function) => visit(address(function)),
+            RuntimeValue::Class(class) => visit(address(class)),
+            RuntimeValue::Instance(instance) => visit(address(instance)),
+            RuntimeValue::List(list) => visit(address(list)),
+            RuntimeValue::Map(map) => visit(address(map)),
+            RuntimeValue::Module(module) => visit(address(module)),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the address which identifies a tracked object.
+pub fn address<T>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
@@ -2,55 +2,254 @@ use std::{
     cell::RefCell,
     collections::HashMap,
     error::Error,
-    fmt, mem,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    iter, mem,
+    path::{Path, PathBuf},
     rc::Rc,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use indexmap::IndexMap;
+
 use crate::{
     ast::{
-        AssignExpr, BinaryExpr, BlockStmt, CallExpr, ClassStmt, ConditionExpr, Expr, ExprVisitor,
-        ExpressionStmt, FunctionStmt, GetExpr, GroupingExpr, IfStmt, LiteralExpr, PrintStmt,
-        ReturnStmt, SetExpr, Stmt, StmtVisitor, SuperExpr, ThisExpr, UnaryExpr, VarStmt,
-        VariableExpr, VisitExpr, VisitStmt, WhileStmt,
+        AssignExpr, BinaryExpr, BlockStmt, BreakStmt, CallExpr, ClassStmt, ConditionExpr,
+        ContinueStmt, ExportStmt, Expr, ExprVisitor, ExpressionStmt, FunctionStmt, GetExpr,
+        GroupingExpr, IfStmt, ImportStmt, IndexExpr, ListExpr, LiteralExpr, MapExpr, PrintStmt,
+        ReturnStmt, SetExpr, SetIndexExpr, Stmt, StmtVisitor, SuperExpr, ThisExpr, ThrowStmt,
+        TryStmt, UnaryExpr, VarStmt, VariableExpr, VisitExpr, VisitStmt, WhileStmt,
     },
     environment::Environment,
-    lox::ErrorCollector,
-    token::{LiteralValue, Token, TokenType},
+    gc::{self, Heap, Trace},
+    lox::{self, ErrorCollector, StackFrame},
+    math,
+    output::Output,
+    strings,
+    token::{LiteralValue, Span, Token, TokenType},
 };
 
+/// The native stack, which has to be left for a call to be made.
+///
+/// Each call of a Lox function recurses through several visitor methods, so
+/// deep recursion in a program exhausts the native stack of the interpreter.
+/// Calls are refused with a runtime error before that happens, and what is
+/// left is enough to evaluate the arguments of a call and to report the error.
+/// How deep a program can recurse therefore depends on the size of the stack
+/// of the thread the interpreter runs on.
+const MIN_REMAINING_STACK: usize = 256 * 1024;
+
 pub struct Interpreter {
     error_collector: Rc<RefCell<ErrorCollector>>,
+    output: Box<dyn Output>,
+    heap: Heap,
+    /// The calls to declared functions, which are in progress, with the
+    /// innermost call last.
+    call_stack: Vec<CallFrame>,
+    /// The native functions, which are visible in every module.
+    builtins: Rc<RefCell<Environment>>,
+    /// The globals of the module which is being executed.
     globals: Rc<RefCell<Environment>>,
     environment: Rc<RefCell<Environment>>,
+    /// The class of the values which runtime errors are caught as.
+    error_class: Rc<Class>,
+    /// The imported modules by their canonical path. A module maps to `None`
+    /// while it is being executed, which is how circular imports are detected.
+    modules: HashMap<PathBuf, Option<Rc<Module>>>,
+    /// The directory, which imports of the module being executed are
+    /// relative to.
+    directory: PathBuf,
 }
 
 impl Interpreter {
-    pub fn new(error_collector: Rc<RefCell<ErrorCollector>>) -> Interpreter {
-        let mut globals = Environment::new();
-        BuiltinFunction::clock().add_to_environment(&mut globals);
-
-        let globals = Rc::new(RefCell::new(globals));
-
-        Interpreter {
+    pub fn new(
+        error_collector: Rc<RefCell<ErrorCollector>>,
+        output: Box<dyn Output>,
+    ) -> Interpreter {
+        let mut heap = Heap::new();
+
+        let builtins = heap.environment(Environment::new());
+        let globals = heap.environment(Environment::new_enclosed(&builtins));
+        let error_class = heap.class(Class {
+            name: "Error".to_string(),
+            super_class: None,
+            methods: HashMap::new(),
+        });
+
+        let mut interpreter = Interpreter {
             error_collector,
+            output,
+            heap,
+            call_stack: vec![],
+            builtins,
             globals: globals.clone(),
             environment: globals,
+            error_class,
+            modules: HashMap::new(),
+            directory: PathBuf::new(),
+        };
+        interpreter.define_builtin(BuiltinFunction::clock());
+        for function in strings::functions().chain(math::functions()) {
+            interpreter.define_builtin(function);
         }
+        interpreter
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Rc<Stmt>>) {
+    pub fn output(&mut self) -> &mut dyn Output {
+        self.output.as_mut()
+    }
+
+    /// Returns the global variables, including native functions, sorted by
+    /// name.
+    pub fn globals(&self) -> Vec<(String, RuntimeValue)> {
+        let mut globals: HashMap<String, RuntimeValue> = HashMap::new();
+        // Globals can shadow native functions.
+        for environment in [&self.builtins, &self.globals] {
+            for (name, value) in environment.borrow().values() {
+                globals.insert(name.clone(), value.clone());
+            }
+        }
+
+        let mut globals: Vec<_> = globals.into_iter().collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        globals
+    }
+
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    pub fn define_builtin(&mut self, function: BuiltinFunction) {
+        let name = function.name.clone();
+        self.builtins
+            .borrow_mut()
+            .define(&name, RuntimeValue::BuiltinFunction(Rc::new(function)));
+    }
+
+    /// Executes `statements`, which are the contents of the file at `path`,
+    /// if they come from a file.
+    ///
+    /// Imports are resolved relative to the directory of `path`, or to the
+    /// working directory otherwise.
+    pub fn interpret(&mut self, statements: &Vec<Rc<Stmt>>, path: Option<&Path>) {
+        // The file can't be imported while it is being executed.
+        let path = path.and_then(|path| fs::canonicalize(path).ok());
+        if let Some(path) = &path {
+            self.modules.insert(path.clone(), None);
+        }
+        let directory = path.as_deref().map_or(PathBuf::new(), directory_of);
+        let enclosing_directory = mem::replace(&mut self.directory, directory);
+
         for statement in statements {
-            if let Err(early_return) = self.execute(statement) {
-                if let EarlyReturn::Error(error) = early_return {
-                    self.error_collector.borrow_mut().runtime_error(error);
-                    return;
-                }
+            if let Err(EarlyReturn::Error(mut error) | EarlyReturn::Throw(_, mut error)) =
+                self.execute(statement)
+            {
+                self.capture_trace(&mut error);
+                self.error_collector.borrow_mut().runtime_error(*error);
+                break;
             }
         }
+
+        self.directory = enclosing_directory;
+        if let Some(path) = &path {
+            self.modules.remove(path);
+        }
+    }
+
+    /// Returns the namespace of the module at `path`, which is executed
+    /// unless it has already been imported.
+    fn import(&mut self, stmt: &ImportStmt) -> Result<Rc<Module>, EarlyReturn> {
+        let error = |message: String| {
+            RuntimeError::new(message, &stmt.keyword)
+                .with_span(stmt.span.clone())
+                .into()
+        };
+
+        let path = match fs::canonicalize(self.directory.join(&stmt.path)) {
+            Ok(path) => path,
+            Err(_) => return error(format!("Could not find module '{}'.", stmt.path)),
+        };
+        match self.modules.get(&path) {
+            Some(Some(module)) => return Ok(module.clone()),
+            Some(None) => return error(format!("Circular import of module '{}'.", stmt.path)),
+            None => {}
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => return error(format!("Could not read module '{}'.", stmt.path)),
+        };
+        let statements = lox::compile(&mut self.error_collector.borrow_mut(), &source, false);
+        let statements = match statements {
+            Some(statements) => statements,
+            // The compile errors have been reported already.
+            None => return error(format!("Could not compile module '{}'.", stmt.path)),
+        };
+
+        self.modules.insert(path.clone(), None);
+        let globals = self
+            .heap
+            .environment(Environment::new_enclosed(&self.builtins));
+        let enclosing_globals = mem::replace(&mut self.globals, globals.clone());
+        let enclosing_directory = mem::replace(&mut self.directory, directory_of(&path));
+        let result = self.execute_block(&statements, &globals);
+        self.globals = enclosing_globals;
+        self.directory = enclosing_directory;
+
+        if let Err(early_return) = result {
+            self.modules.remove(&path);
+            return Err(early_return);
+        }
+
+        let exports = statements
+            .iter()
+            .filter_map(|statement| match statement.as_ref() {
+                Stmt::Export(stmt) => Some(stmt.name()),
+                _ => None,
+            })
+            .map(|name| {
+                (
+                    name.lexeme.clone(),
+                    globals.borrow_mut().get_at(&name.lexeme, 0),
+                )
+            })
+            .collect();
+        let module = self.heap.module(Module {
+            name: stmt.name.clone(),
+            exports,
+        });
+        self.modules.insert(path, Some(module.clone()));
+        Ok(module)
+    }
+
+    /// Records the calls, which are in progress, as the stack trace of
+    /// `error`, unless it already has one from a deeper call.
+    fn capture_trace(&self, error: &mut RuntimeError) {
+        if !error.trace.is_empty() {
+            return;
+        }
+
+        let mut line = error.token.line;
+        for frame in self.call_stack.iter().rev() {
+            error.trace.push(StackFrame {
+                function: Some(frame.function.qualified_name()),
+                line,
+            });
+            line = frame.line;
+        }
+        error.trace.push(StackFrame {
+            function: None,
+            line,
+        });
     }
 
     fn execute(&mut self, stmt: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        // Statement boundaries are a safe point to collect, because no
+        // environment or instance is borrowed at this point.
+        if self.heap.should_collect() {
+            self.heap.collect();
+        }
+
         stmt.accept(self)
     }
 
@@ -82,6 +281,26 @@ impl Interpreter {
         result
     }
 
+    /// Executes `statements` in a new scope.
+    fn execute_scope(&mut self, statements: &Vec<Rc<Stmt>>) -> Result<(), EarlyReturn> {
+        let environment = self
+            .heap
+            .environment(Environment::new_enclosed(&self.environment));
+        self.execute_block(statements, &environment)
+    }
+
+    /// Returns the value a `catch` clause receives for `error`, which is an
+    /// instance of `Error` with the message and line of the error.
+    fn error_value(&mut self, error: &RuntimeError) -> RuntimeValue {
+        let mut instance = Instance::new(self.error_class.clone());
+        instance.set(
+            "message",
+            RuntimeValue::String(Rc::new(error.message.clone())),
+        );
+        instance.set("line", RuntimeValue::Number(error.token.line as f64));
+        RuntimeValue::Instance(self.heap.instance(instance))
+    }
+
     fn evaluate(&mut self, expr: &Rc<Expr>) -> Result<RuntimeValue, EarlyReturn> {
         expr.accept(self)
     }
@@ -109,6 +328,17 @@ impl Interpreter {
     }
 }
 
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // Globals commonly form cycles with the functions declared in them,
+        // which the collector cannot free while the interpreter references
+        // the globals. The same goes for the modules.
+        self.globals.borrow_mut().clear();
+        self.modules.clear();
+        self.heap.collect();
+    }
+}
+
 impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
     fn visit_expression_stmt(
         &mut self,
@@ -119,8 +349,7 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
     }
 
     fn visit_block_stmt(&mut self, stmt: &BlockStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
-        let environment = Rc::new(RefCell::new(Environment::new_enclosed(&self.environment)));
-        self.execute_block(&stmt.statements, &environment)
+        self.execute_scope(&stmt.statements)
     }
 
     fn visit_var_stmt(&mut self, stmt: &VarStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
@@ -136,10 +365,12 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
         stmt: &FunctionStmt,
         ptr: &Rc<Stmt>,
     ) -> Result<(), EarlyReturn> {
-        let function = RuntimeValue::DeclaredFunction(Rc::new(DeclaredFunction {
+        let function = RuntimeValue::DeclaredFunction(self.heap.function(DeclaredFunction {
             declaration: ptr.clone(),
             closure: self.environment.clone(),
+            globals: self.globals.clone(),
             is_initializer: false,
+            class: None,
         }));
         self.environment
             .borrow_mut()
@@ -150,7 +381,7 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
     fn visit_class_stmt(&mut self, stmt: &ClassStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
         self.environment
             .borrow_mut()
-            .define(&&stmt.name.lexeme, RuntimeValue::Nil);
+            .define(&stmt.name.lexeme, RuntimeValue::Nil);
 
         let mut method_environment = self.environment.clone();
         let mut super_class = None;
@@ -160,31 +391,34 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
                 RuntimeValue::Class(class) => {
                     let mut environment = Environment::new_enclosed(&self.environment);
                     environment.define("super", RuntimeValue::Class(class.clone()));
-                    method_environment = Rc::new(RefCell::new(environment));
+                    method_environment = self.heap.environment(environment);
                     super_class = Some(class);
                 }
                 _ => {
-                    return RuntimeError {
-                        message: "Super class must be a class".to_string(),
-                        token: super_class_expr.as_variable().name.clone(),
-                    }
+                    return RuntimeError::new(
+                        "Super class must be a class".to_string(),
+                        &super_class_expr.as_variable().name,
+                    )
                     .into()
                 }
             }
         }
 
         let mut methods: HashMap<String, Rc<DeclaredFunction>> = HashMap::new();
+        let class_name: Rc<str> = Rc::from(stmt.name.lexeme.as_str());
         for method in &stmt.methods {
             let name = &method.as_function().name.lexeme;
-            let function = Rc::new(DeclaredFunction {
+            let function = self.heap.function(DeclaredFunction {
                 declaration: method.clone(),
                 closure: method_environment.clone(),
+                globals: self.globals.clone(),
                 is_initializer: name == "init",
+                class: Some(class_name.clone()),
             });
             methods.insert(name.clone(), function);
         }
 
-        let class = RuntimeValue::Class(Rc::new(Class {
+        let class = RuntimeValue::Class(self.heap.class(Class {
             name: stmt.name.lexeme.to_string(),
             super_class,
             methods,
@@ -195,7 +429,7 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
 
     fn visit_print_stmt(&mut self, stmt: &PrintStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
         let value = self.evaluate(&stmt.expression)?;
-        println!("{}", value);
+        self.output.print(&value.to_string());
         Ok(())
     }
 
@@ -209,7 +443,12 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
 
     fn visit_while_stmt(&mut self, stmt: &WhileStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
         while self.evaluate(&stmt.condition)?.is_truthy() {
-            self.execute(&stmt.body)?;
+            match self.execute(&stmt.body) {
+                Ok(()) | Err(EarlyReturn::Continue) => {}
+                Err(EarlyReturn::Break) => break,
+                Err(early_return) => return Err(early_return),
+            }
+            self.evaluate_optional(&stmt.increment)?;
         }
         Ok(())
     }
@@ -217,6 +456,61 @@ impl StmtVisitor<Result<(), EarlyReturn>> for Interpreter {
     fn visit_return_stmt(&mut self, stmt: &ReturnStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
         self.evaluate_optional(&stmt.value)?.into()
     }
+
+    fn visit_break_stmt(&mut self, _: &BreakStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        Err(EarlyReturn::Break)
+    }
+
+    fn visit_continue_stmt(&mut self, _: &ContinueStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        Err(EarlyReturn::Continue)
+    }
+
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        let module = self.import(stmt)?;
+        self.environment
+            .borrow_mut()
+            .define(&stmt.name, RuntimeValue::Module(module));
+        Ok(())
+    }
+
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        self.execute(&stmt.declaration)
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        let value = self.evaluate(&stmt.value)?;
+        let error = RuntimeError::new(format!("Uncaught exception: {}", value), &stmt.token)
+            .with_span(stmt.token.span.to(&stmt.value.span()));
+        Err(EarlyReturn::Throw(value, Box::new(error)))
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt, _: &Rc<Stmt>) -> Result<(), EarlyReturn> {
+        let mut result = self.execute_scope(&stmt.body);
+
+        if let Some(catch) = &stmt.catch {
+            let value = match result {
+                Err(EarlyReturn::Throw(ref value, _)) => Some(value.clone()),
+                Err(EarlyReturn::Error(ref error)) => Some(self.error_value(error)),
+                _ => None,
+            };
+
+            if let Some(value) = value {
+                let environment = self
+                    .heap
+                    .environment(Environment::new_enclosed(&self.environment));
+                environment.borrow_mut().define(&catch.name.lexeme, value);
+                result = self.execute_block(&catch.body, &environment);
+            }
+        }
+
+        if let Some(finally) = &stmt.finally {
+            // Leaving the finally block early replaces the way the rest of
+            // the statement was left.
+            self.execute_scope(finally)?;
+        }
+
+        result
+    }
 }
 
 impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
@@ -304,12 +598,10 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
                 match result {
                     Some(result) => result,
                     None => {
-                        return RuntimeError {
-                            message: format!(
-                                "Operands must either both be numbers or both be strings."
-                            ),
-                            token: expr.operator.clone(),
-                        }
+                        return RuntimeError::new(
+                            "Operands must either both be numbers or both be strings.".to_string(),
+                            &expr.operator,
+                        )
                         .into();
                     }
                 }
@@ -326,6 +618,10 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
                 let (left, right) = check_numeric_operands(&expr.operator, &left, &right)?;
                 RuntimeValue::Number(left * right)
             }
+            TokenType::Percent => {
+                let (left, right) = check_numeric_operands(&expr.operator, &left, &right)?;
+                RuntimeValue::Number(left % right)
+            }
             TokenType::EqualEqual => RuntimeValue::Bool(left == right),
             TokenType::BangEqual => RuntimeValue::Bool(left != right),
             TokenType::Less => {
@@ -355,23 +651,16 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
     ) -> Result<RuntimeValue, EarlyReturn> {
         let left = self.evaluate(&expr.left)?;
 
-        Ok(RuntimeValue::Bool(match expr.operator.token_type {
-            TokenType::Or => {
-                if left.is_truthy() {
-                    true
-                } else {
-                    self.evaluate(&expr.right)?.is_truthy()
-                }
-            }
-            TokenType::And => {
-                if !left.is_truthy() {
-                    false
-                } else {
-                    self.evaluate(&expr.right)?.is_truthy()
-                }
-            }
+        // The result is the operand which decided the condition.
+        let is_decided = match expr.operator.token_type {
+            TokenType::Or => left.is_truthy(),
+            TokenType::And => !left.is_truthy(),
             _ => panic!(),
-        }))
+        };
+        match is_decided {
+            true => Ok(left),
+            false => self.evaluate(&expr.right),
+        }
     }
 
     fn visit_grouping_expr(
@@ -389,37 +678,42 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
     ) -> Result<RuntimeValue, EarlyReturn> {
         let callee = self.evaluate(&expr.callee)?;
 
+        let mut arguments = vec![];
+        for argument in &expr.arguments {
+            arguments.push(self.evaluate(argument)?);
+        }
+
         let callable: &dyn Callable = match &callee {
             RuntimeValue::BuiltinFunction(function) => function,
             RuntimeValue::DeclaredFunction(function) => function,
             RuntimeValue::Class(function) => function,
             _ => {
-                return RuntimeError {
-                    message: "Can only call functions and classes.".to_string(),
-                    token: expr.paren.clone(),
-                }
+                return RuntimeError::new(
+                    "Can only call functions and classes.".to_string(),
+                    &expr.paren,
+                )
+                .with_span(expr.callee.span())
                 .into();
             }
         };
 
         if expr.arguments.len() != callable.arity() as usize {
-            return RuntimeError {
-                message: format!(
+            return RuntimeError::new(
+                format!(
                     "Expected {} arguments but got {}.",
                     callable.arity(),
                     expr.arguments.len()
                 ),
-                token: expr.paren.clone(),
-            }
+                &expr.paren,
+            )
             .into();
         };
 
-        let mut arguments = vec![];
-        for argument in &expr.arguments {
-            arguments.push(self.evaluate(argument)?);
+        if stacker::remaining_stack().is_some_and(|remaining| remaining < MIN_REMAINING_STACK) {
+            return RuntimeError::new("Stack overflow.".to_string(), &expr.paren).into();
         }
 
-        callable.call(self, arguments)
+        callable.call(self, &expr.paren, arguments)
     }
 
     fn visit_get_expr(
@@ -430,12 +724,20 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
         let object = self.evaluate(&expr.object)?;
 
         match object {
-            RuntimeValue::Instance(instance) => instance.get(&expr.name),
-            _ => RuntimeError {
-                message: "Only instances have properties.".to_string(),
-                token: expr.name.clone(),
-            }
-            .into(),
+            RuntimeValue::String(string) => match strings::method(&string, &expr.name.lexeme) {
+                Some(method) => Ok(method),
+                None => {
+                    let message = format!("Undefined property '{}'.", expr.name.lexeme);
+                    RuntimeError::new(message, &expr.name).into()
+                }
+            },
+            RuntimeValue::Instance(instance) => instance.get(&expr.name, &mut self.heap),
+            RuntimeValue::List(list) => List::method(&list, &expr.name, &mut self.heap),
+            RuntimeValue::Map(map) => Map::method(&map, &expr.name, &mut self.heap),
+            RuntimeValue::Module(module) => module.get(&expr.name),
+            _ => RuntimeError::new("Only instances have properties.".to_string(), &expr.name)
+                .with_span(expr.object.span())
+                .into(),
         }
     }
 
@@ -453,10 +755,100 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
                 instance.borrow_mut().set(&expr.name.lexeme, value);
                 Ok(result)
             }
-            _ => RuntimeError {
-                message: "Only instances have properties.".to_string(),
-                token: expr.name.clone(),
+            _ => RuntimeError::new("Only instances have properties.".to_string(), &expr.name)
+                .with_span(expr.object.span())
+                .into(),
+        }
+    }
+
+    fn visit_list_expr(
+        &mut self,
+        expr: &ListExpr,
+        _: &Rc<Expr>,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let mut elements = Vec::with_capacity(expr.elements.len());
+        for element in &expr.elements {
+            elements.push(self.evaluate(element)?);
+        }
+
+        Ok(RuntimeValue::List(self.heap.list(List { elements })))
+    }
+
+    fn visit_map_expr(
+        &mut self,
+        expr: &MapExpr,
+        _: &Rc<Expr>,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let mut map = Map::new();
+        for (key_expr, value_expr) in &expr.entries {
+            let key = self.evaluate(key_expr)?;
+            let value = self.evaluate(value_expr)?;
+            if let Err(message) = map.insert(&key, value) {
+                return RuntimeError::new(message, &expr.brace)
+                    .with_span(key_expr.span())
+                    .into();
+            }
+        }
+
+        Ok(RuntimeValue::Map(self.heap.map(map)))
+    }
+
+    fn visit_index_expr(
+        &mut self,
+        expr: &IndexExpr,
+        _: &Rc<Expr>,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let object = self.evaluate(&expr.object)?;
+        let index = self.evaluate(&expr.index)?;
+
+        match object {
+            RuntimeValue::List(list) => {
+                let list = list.borrow();
+                match sequence_index(&index, list.elements.len()) {
+                    Ok(index) => Ok(list.elements[index].clone()),
+                    Err(message) => index_error(message, &expr.bracket, &expr.index),
+                }
+            }
+            RuntimeValue::Map(map) => map
+                .borrow()
+                .get(&index)
+                .or_else(|message| index_error(message, &expr.bracket, &expr.index)),
+            _ => RuntimeError::new(
+                "Only lists and maps can be indexed.".to_string(),
+                &expr.bracket,
+            )
+            .with_span(expr.object.span())
+            .into(),
+        }
+    }
+
+    fn visit_set_index_expr(
+        &mut self,
+        expr: &SetIndexExpr,
+        _: &Rc<Expr>,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let object = self.evaluate(&expr.object)?;
+        let index = self.evaluate(&expr.index)?;
+        let value = self.evaluate(&expr.value)?;
+
+        match object {
+            RuntimeValue::List(list) => {
+                let mut list = list.borrow_mut();
+                match sequence_index(&index, list.elements.len()) {
+                    Ok(index) => list.elements[index] = value.clone(),
+                    Err(message) => return index_error(message, &expr.bracket, &expr.index),
+                }
+                Ok(value)
             }
+            RuntimeValue::Map(map) => match map.borrow_mut().insert(&index, value.clone()) {
+                Ok(()) => Ok(value),
+                Err(message) => index_error(message, &expr.bracket, &expr.index),
+            },
+            _ => RuntimeError::new(
+                "Only lists and maps can be indexed.".to_string(),
+                &expr.bracket,
+            )
+            .with_span(expr.object.span())
             .into(),
         }
     }
@@ -485,12 +877,14 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
                 let instance = environment
                     .get_at("this", scope_index - 1)
                     .unwrap_instance();
-                Ok(RuntimeValue::DeclaredFunction(method.bind(&instance)))
-            }
-            None => RuntimeError {
-                message: format!("Undefined property '{}'.", expr.method.lexeme),
-                token: expr.method.clone(),
+                Ok(RuntimeValue::DeclaredFunction(
+                    method.bind(&instance, &mut self.heap),
+                ))
             }
+            None => RuntimeError::new(
+                format!("Undefined property '{}'.", expr.method.lexeme),
+                &expr.method,
+            )
             .into(),
         }
     }
@@ -498,7 +892,15 @@ impl ExprVisitor<Result<RuntimeValue, EarlyReturn>> for Interpreter {
 
 pub enum EarlyReturn {
     Return(RuntimeValue),
-    Error(RuntimeError),
+    /// Exits the innermost loop. The resolver ensures that there is one.
+    Break,
+    /// Ends the current iteration of the innermost loop.
+    Continue,
+    /// A value thrown by a `throw` statement, with the error which is
+    /// reported if no `catch` clause catches it.
+    Throw(RuntimeValue, Box<RuntimeError>),
+    // Boxed, to keep results which can hold an early return small.
+    Error(Box<RuntimeError>),
 }
 
 impl Error for EarlyReturn {}
@@ -515,22 +917,43 @@ impl fmt::Display for EarlyReturn {
     }
 }
 
-impl<T> Into<Result<T, EarlyReturn>> for RuntimeValue {
-    fn into(self) -> Result<T, EarlyReturn> {
-        Err(EarlyReturn::Return(self))
+impl<T> From<RuntimeValue> for Result<T, EarlyReturn> {
+    fn from(value: RuntimeValue) -> Self {
+        Err(EarlyReturn::Return(value))
     }
 }
 
-impl<T> Into<Result<T, EarlyReturn>> for RuntimeError {
-    fn into(self) -> Result<T, EarlyReturn> {
-        Err(EarlyReturn::Error(self))
+impl<T> From<RuntimeError> for Result<T, EarlyReturn> {
+    fn from(error: RuntimeError) -> Self {
+        Err(EarlyReturn::Error(Box::new(error)))
     }
 }
 
 #[derive(Debug)]
 pub struct RuntimeError {
     pub message: String,
+    /// The token the error is reported at.
     pub token: Token,
+    /// The code which caused the error, which can be more than `token`.
+    pub span: Span,
+    /// The calls which were in progress when the error occurred, innermost
+    /// first. Empty until the error leaves the call in which it occurred.
+    pub trace: Vec<StackFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(message: String, token: &Token) -> RuntimeError {
+        RuntimeError {
+            message,
+            token: token.clone(),
+            span: token.span.clone(),
+            trace: vec![],
+        }
+    }
+
+    fn with_span(self, span: Span) -> RuntimeError {
+        RuntimeError { span, ..self }
+    }
 }
 
 impl Error for RuntimeError {}
@@ -551,6 +974,9 @@ pub enum RuntimeValue {
     DeclaredFunction(Rc<DeclaredFunction>),
     Class(Rc<Class>),
     Instance(Rc<RefCell<Instance>>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+    Module(Rc<Module>),
 }
 
 impl RuntimeValue {
@@ -568,10 +994,25 @@ impl RuntimeValue {
         }
     }
 
+    fn as_list(&self) -> &Rc<RefCell<List>> {
+        match self {
+            RuntimeValue::List(value) => value,
+            _ => panic!(),
+        }
+    }
+
+    fn as_map(&self) -> &Rc<RefCell<Map>> {
+        match self {
+            RuntimeValue::Map(value) => value,
+            _ => panic!(),
+        }
+    }
+
     fn is_truthy(&self) -> bool {
         match self {
+            RuntimeValue::Nil => false,
             RuntimeValue::Bool(value) => *value,
-            _ => false,
+            _ => true,
         }
     }
 }
@@ -592,6 +1033,18 @@ impl fmt::Display for RuntimeValue {
             DeclaredFunction(value) => write!(f, "{}", value),
             Class(value) => write!(f, "{}", value),
             Instance(value) => write!(f, "{}", value.borrow()),
+            // The list stays borrowed mutably while it is formatted, so that a
+            // list, which contains itself, is detected instead of formatted
+            // forever.
+            List(value) => match value.try_borrow_mut() {
+                Ok(list) => write!(f, "{}", list),
+                Err(_) => write!(f, "[...]"),
+            },
+            Map(value) => match value.try_borrow_mut() {
+                Ok(map) => write!(f, "{}", map),
+                Err(_) => write!(f, "{{...}}"),
+            },
+            Module(value) => write!(f, "{}", value),
         }
     }
 }
@@ -601,11 +1054,7 @@ fn check_numeric_operand(operator: &Token, operand: &RuntimeValue) -> Result<f64
         return Ok(value);
     }
 
-    RuntimeError {
-        message: format!("Operand must be a number."),
-        token: operator.clone(),
-    }
-    .into()
+    RuntimeError::new("Operand must be a number.".to_string(), operator).into()
 }
 
 fn check_numeric_operands(
@@ -619,27 +1068,67 @@ fn check_numeric_operands(
         }
     }
 
-    RuntimeError {
-        message: format!("Operands must both be numbers."),
-        token: operator.clone(),
+    RuntimeError::new("Operands must both be numbers.".to_string(), operator).into()
+}
+
+/// Returns `index` as a position in a sequence of `length` elements, or an
+/// error message if it is not one.
+fn sequence_index(index: &RuntimeValue, length: usize) -> Result<usize, String> {
+    match *index {
+        RuntimeValue::Number(value) if value.fract() == 0.0 => {
+            if value < 0.0 || value >= length as f64 {
+                Err(format!(
+                    "Index {} is out of range for a list of length {}.",
+                    index, length
+                ))
+            } else {
+                Ok(value as usize)
+            }
+        }
+        _ => Err("Index must be an integer.".to_string()),
+    }
+}
+
+/// Returns an error with `message` for an index expression, which is reported
+/// at its `index`.
+fn index_error<T>(message: String, bracket: &Token, index: &Rc<Expr>) -> Result<T, EarlyReturn> {
+    RuntimeError::new(message, bracket)
+        .with_span(index.span())
+        .into()
+}
+
+/// Formats `value` as an element of a list or map, with strings in quotes.
+fn fmt_element(f: &mut fmt::Formatter<'_>, value: &RuntimeValue) -> fmt::Result {
+    match value {
+        RuntimeValue::String(value) => write!(f, "\"{}\"", value),
+        _ => write!(f, "{}", value),
     }
-    .into()
 }
 
 trait Callable: fmt::Display {
     fn arity(&self) -> u8;
 
+    /// Calls this callable with `arguments`. Errors which are not attributable
+    /// to a more specific token are reported at `paren`, the closing
+    /// parenthesis of the call.
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<RuntimeValue>,
     ) -> Result<RuntimeValue, EarlyReturn>;
 }
 
+type NativeFunction = dyn Fn(&mut Heap, &[RuntimeValue]) -> Result<RuntimeValue, String>;
+
 pub struct BuiltinFunction {
-    name: &'static str,
+    name: String,
     arity: u8,
-    function: fn(arguments: Vec<RuntimeValue>) -> RuntimeValue,
+    function: Box<NativeFunction>,
+    /// The value the function is bound to, which is passed as the first
+    /// argument. It is kept here instead of in the closure, so the cycle
+    /// collector can trace it.
+    receiver: Option<RuntimeValue>,
 }
 
 impl Callable for Rc<BuiltinFunction> {
@@ -649,10 +1138,24 @@ impl Callable for Rc<BuiltinFunction> {
 
     fn call(
         &self,
-        _: &mut Interpreter,
+        interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<RuntimeValue>,
     ) -> Result<RuntimeValue, EarlyReturn> {
-        Ok((self.function)(arguments))
+        let arguments = match &self.receiver {
+            Some(receiver) => iter::once(receiver.clone()).chain(arguments).collect(),
+            None => arguments,
+        };
+        (self.function)(&mut interpreter.heap, &arguments)
+            .or_else(|message| RuntimeError::new(message, paren).into())
+    }
+}
+
+impl Trace for BuiltinFunction {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(receiver) = &self.receiver {
+            receiver.trace(visit);
+        }
     }
 }
 
@@ -669,42 +1172,82 @@ impl fmt::Display for BuiltinFunction {
 }
 
 impl BuiltinFunction {
-    fn clock() -> BuiltinFunction {
+    pub fn new<F>(name: &str, arity: u8, function: F) -> BuiltinFunction
+    where
+        F: Fn(&[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        BuiltinFunction::with_heap(name, arity, move |_, arguments| function(arguments))
+    }
+
+    /// Creates a function like [BuiltinFunction::new], whose implementation
+    /// can allocate objects on the heap.
+    pub(crate) fn with_heap<F>(name: &str, arity: u8, function: F) -> BuiltinFunction
+    where
+        F: Fn(&mut Heap, &[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
         BuiltinFunction {
-            name: "clock",
-            arity: 0,
-            function: |_| {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as f64
-                    / 1000.0;
-                RuntimeValue::Number(now)
-            },
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+            receiver: None,
         }
     }
 
-    fn add_to_environment(self, environment: &mut Environment) {
-        environment.define(self.name, RuntimeValue::BuiltinFunction(Rc::new(self)));
+    /// Returns a function, which calls this function with `receiver` as the
+    /// first argument, followed by its own arguments.
+    pub(crate) fn bind(self, receiver: RuntimeValue) -> BuiltinFunction {
+        BuiltinFunction {
+            arity: self.arity - 1,
+            receiver: Some(receiver),
+            ..self
+        }
+    }
+
+    fn clock() -> BuiltinFunction {
+        BuiltinFunction::new("clock", 0, |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as f64
+                / 1000.0;
+            Ok(RuntimeValue::Number(now))
+        })
     }
 }
 
 pub struct DeclaredFunction {
     declaration: Rc<Stmt>,
     closure: Rc<RefCell<Environment>>,
+    /// The globals of the module the function was declared in.
+    globals: Rc<RefCell<Environment>>,
     is_initializer: bool,
+    /// The name of the class, if the function is a method.
+    class: Option<Rc<str>>,
 }
 
 impl DeclaredFunction {
-    fn bind(&self, instance: &Rc<RefCell<Instance>>) -> Rc<DeclaredFunction> {
+    /// Returns the name of the function, prefixed with the name of its class
+    /// for methods.
+    fn qualified_name(&self) -> String {
+        let name = &self.declaration.as_function().name.lexeme;
+        match &self.class {
+            Some(class) => format!("{}.{}", class, name),
+            None => name.clone(),
+        }
+    }
+
+    fn bind(&self, instance: &Rc<RefCell<Instance>>, heap: &mut Heap) -> Rc<DeclaredFunction> {
         let mut environment = Environment::new_enclosed(&self.closure);
 
         environment.define("this", RuntimeValue::Instance(instance.clone()));
+        let closure = heap.environment(environment);
 
-        Rc::new(DeclaredFunction {
+        heap.function(DeclaredFunction {
             declaration: self.declaration.clone(),
-            closure: Rc::new(RefCell::new(environment)),
+            closure,
+            globals: self.globals.clone(),
             is_initializer: self.is_initializer,
+            class: self.class.clone(),
         })
     }
 }
@@ -717,6 +1260,7 @@ impl Callable for Rc<DeclaredFunction> {
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<RuntimeValue>,
     ) -> Result<RuntimeValue, EarlyReturn> {
         let mut environment = Environment::new_enclosed(&self.closure);
@@ -726,9 +1270,20 @@ impl Callable for Rc<DeclaredFunction> {
             environment.define(&parameter.lexeme, argument);
         }
 
-        if let Err(early_return) =
-            interpreter.execute_block(&function.body, &Rc::new(RefCell::new(environment)))
-        {
+        let environment = interpreter.heap.environment(environment);
+        interpreter.call_stack.push(CallFrame {
+            function: self.clone(),
+            line: paren.line,
+        });
+        let enclosing_globals = mem::replace(&mut interpreter.globals, self.globals.clone());
+        let mut result = interpreter.execute_block(&function.body, &environment);
+        if let Err(EarlyReturn::Error(error) | EarlyReturn::Throw(_, error)) = &mut result {
+            interpreter.capture_trace(error);
+        }
+        interpreter.globals = enclosing_globals;
+        interpreter.call_stack.pop();
+
+        if let Err(early_return) = result {
             match early_return {
                 EarlyReturn::Return(value) => {
                     return Ok(match self.is_initializer {
@@ -736,7 +1291,7 @@ impl Callable for Rc<DeclaredFunction> {
                         false => value,
                     })
                 }
-                EarlyReturn::Error(error) => return error.into(),
+                early_return => return Err(early_return),
             }
         }
 
@@ -747,6 +1302,13 @@ impl Callable for Rc<DeclaredFunction> {
     }
 }
 
+impl Trace for DeclaredFunction {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::address(&self.closure));
+        visit(gc::address(&self.globals));
+    }
+}
+
 impl PartialEq for DeclaredFunction {
     fn eq(&self, other: &Self) -> bool {
         std::ptr::eq(self, other)
@@ -759,6 +1321,13 @@ impl fmt::Display for DeclaredFunction {
     }
 }
 
+/// A call to a [DeclaredFunction], which is in progress.
+struct CallFrame {
+    function: Rc<DeclaredFunction>,
+    /// The line from which the function was called.
+    line: usize,
+}
+
 pub struct Class {
     name: String,
     super_class: Option<Rc<Class>>,
@@ -767,14 +1336,13 @@ pub struct Class {
 
 impl Class {
     fn find_method(&self, name: &str) -> Option<Rc<DeclaredFunction>> {
-        if let x @ Some(_) = self.methods.get(name).map(|method| method.clone()) {
+        if let x @ Some(_) = self.methods.get(name).cloned() {
             return x;
         };
 
         self.super_class
             .as_ref()
-            .map(|super_class| super_class.find_method(name))
-            .flatten()
+            .and_then(|super_class| super_class.find_method(name))
     }
 }
 
@@ -788,18 +1356,31 @@ impl Callable for Rc<Class> {
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<RuntimeValue>,
     ) -> Result<RuntimeValue, EarlyReturn> {
-        let instance = Rc::new(RefCell::new(Instance::new(self.clone())));
+        let instance = interpreter.heap.instance(Instance::new(self.clone()));
 
         if let Some(init) = self.find_method("init") {
-            init.bind(&instance).call(interpreter, arguments)?;
+            init.bind(&instance, &mut interpreter.heap)
+                .call(interpreter, paren, arguments)?;
         }
 
         Ok(RuntimeValue::Instance(instance))
     }
 }
 
+impl Trace for Class {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(super_class) = &self.super_class {
+            visit(gc::address(super_class));
+        }
+        for method in self.methods.values() {
+            visit(gc::address(method));
+        }
+    }
+}
+
 impl PartialEq for Class {
     fn eq(&self, other: &Self) -> bool {
         std::ptr::eq(self, other)
@@ -828,27 +1409,37 @@ impl Instance {
     fn set(&mut self, name: &str, value: RuntimeValue) {
         self.fields.insert(name.to_string(), value);
     }
+
+    /// Removes all fields from this instance and returns them.
+    pub fn clear(&mut self) -> HashMap<String, RuntimeValue> {
+        mem::take(&mut self.fields)
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::address(&self.class));
+        for value in self.fields.values() {
+            value.trace(visit);
+        }
+    }
 }
 
 trait InstanceGet {
-    fn get(&self, name: &Token) -> Result<RuntimeValue, EarlyReturn>;
+    fn get(&self, name: &Token, heap: &mut Heap) -> Result<RuntimeValue, EarlyReturn>;
 }
 
 impl InstanceGet for Rc<RefCell<Instance>> {
-    fn get(&self, name: &Token) -> Result<RuntimeValue, EarlyReturn> {
+    fn get(&self, name: &Token, heap: &mut Heap) -> Result<RuntimeValue, EarlyReturn> {
         if let Some(value) = self.borrow().fields.get(&name.lexeme) {
             return Ok(value.clone());
         }
 
         if let Some(method) = self.borrow().class.find_method(&name.lexeme) {
-            return Ok(RuntimeValue::DeclaredFunction(method.bind(self)));
+            return Ok(RuntimeValue::DeclaredFunction(method.bind(self, heap)));
         }
 
-        RuntimeError {
-            message: format!("Undefined property '{}'.", name.lexeme),
-            token: name.clone(),
-        }
-        .into()
+        RuntimeError::new(format!("Undefined property '{}'.", name.lexeme), name).into()
     }
 }
 
@@ -863,3 +1454,322 @@ impl fmt::Display for Instance {
         write!(f, "<{} instance>", self.class.name)
     }
 }
+
+pub struct List {
+    elements: Vec<RuntimeValue>,
+}
+
+impl List {
+    pub fn new(elements: Vec<RuntimeValue>) -> List {
+        List { elements }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Returns the native method `name`, bound to `list`.
+    fn method(
+        list: &Rc<RefCell<List>>,
+        name: &Token,
+        heap: &mut Heap,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let method = match name.lexeme.as_str() {
+            "len" => BuiltinFunction::new("len", 1, |arguments| {
+                let list = arguments[0].as_list().borrow();
+                Ok(RuntimeValue::Number(list.elements.len() as f64))
+            }),
+            "push" => BuiltinFunction::new("push", 2, |arguments| {
+                let mut list = arguments[0].as_list().borrow_mut();
+                list.elements.push(arguments[1].clone());
+                Ok(RuntimeValue::Nil)
+            }),
+            "pop" => BuiltinFunction::new("pop", 1, |arguments| {
+                let mut list = arguments[0].as_list().borrow_mut();
+                list.elements
+                    .pop()
+                    .ok_or_else(|| "Can't pop from an empty list.".to_string())
+            }),
+            "insert" => BuiltinFunction::new("insert", 3, |arguments| {
+                let mut list = arguments[0].as_list().borrow_mut();
+                let length = list.elements.len();
+                let index = match arguments[1] {
+                    // Inserting after the last element is allowed.
+                    RuntimeValue::Number(index) if index == length as f64 => length,
+                    ref index => sequence_index(index, length)?,
+                };
+                list.elements.insert(index, arguments[2].clone());
+                Ok(RuntimeValue::Nil)
+            }),
+            "remove" => BuiltinFunction::new("remove", 2, |arguments| {
+                let mut list = arguments[0].as_list().borrow_mut();
+                let index = sequence_index(&arguments[1], list.elements.len())?;
+                Ok(list.elements.remove(index))
+            }),
+            _ => {
+                return RuntimeError::new(format!("Undefined property '{}'.", name.lexeme), name)
+                    .into()
+            }
+        };
+
+        let method = method.bind(RuntimeValue::List(list.clone()));
+        Ok(RuntimeValue::BuiltinFunction(heap.native(method)))
+    }
+
+    /// Removes all elements from this list and returns them.
+    pub fn clear(&mut self) -> Vec<RuntimeValue> {
+        mem::take(&mut self.elements)
+    }
+}
+
+impl Trace for List {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for element in &self.elements {
+            element.trace(visit);
+        }
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Formats the list like a list literal, with strings in quotes.
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (index, element) in self.elements.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            fmt_element(f, element)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A value, which can be used as a key of a [Map].
+///
+/// Keys are equal if the values they were created from are equal.
+#[derive(Clone)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    /// A number, which is not NaN, since NaN is not equal to itself.
+    Number(f64),
+    String(Rc<String>),
+}
+
+impl TryFrom<&RuntimeValue> for MapKey {
+    type Error = String;
+
+    fn try_from(value: &RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Nil => Ok(MapKey::Nil),
+            RuntimeValue::Bool(value) => Ok(MapKey::Bool(*value)),
+            RuntimeValue::Number(value) if value.is_nan() => {
+                Err("Map key can't be NaN.".to_string())
+            }
+            RuntimeValue::Number(value) => Ok(MapKey::Number(*value)),
+            RuntimeValue::String(value) => Ok(MapKey::String(value.clone())),
+            _ => Err("Map key must be a string, number, boolean or nil.".to_string()),
+        }
+    }
+}
+
+impl From<&MapKey> for RuntimeValue {
+    fn from(key: &MapKey) -> Self {
+        match key {
+            MapKey::Nil => RuntimeValue::Nil,
+            MapKey::Bool(value) => RuntimeValue::Bool(*value),
+            MapKey::Number(value) => RuntimeValue::Number(*value),
+            MapKey::String(value) => RuntimeValue::String(value.clone()),
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        RuntimeValue::from(self) == RuntimeValue::from(other)
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            MapKey::Nil => state.write_u8(0),
+            MapKey::Bool(value) => value.hash(state),
+            // 0 and -0 are equal, so they have to have the same hash.
+            MapKey::Number(value) if *value == 0.0 => 0.0f64.to_bits().hash(state),
+            MapKey::Number(value) => value.to_bits().hash(state),
+            MapKey::String(value) => value.hash(state),
+        }
+    }
+}
+
+/// A map from values to values, which keeps its entries in the order they
+/// were inserted.
+pub struct Map {
+    entries: IndexMap<MapKey, RuntimeValue>,
+}
+
+impl Map {
+    fn new() -> Map {
+        Map {
+            entries: IndexMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&self, key: &RuntimeValue) -> Result<RuntimeValue, String> {
+        self.entries
+            .get(&MapKey::try_from(key)?)
+            .cloned()
+            .ok_or_else(|| undefined_key(key))
+    }
+
+    fn insert(&mut self, key: &RuntimeValue, value: RuntimeValue) -> Result<(), String> {
+        self.entries.insert(MapKey::try_from(key)?, value);
+        Ok(())
+    }
+
+    /// Returns the native method `name`, bound to `map`.
+    ///
+    /// `keys` and `values` return new lists, so a map can be changed while
+    /// iterating over them.
+    fn method(
+        map: &Rc<RefCell<Map>>,
+        name: &Token,
+        heap: &mut Heap,
+    ) -> Result<RuntimeValue, EarlyReturn> {
+        let method = match name.lexeme.as_str() {
+            "len" => BuiltinFunction::new("len", 1, |arguments| {
+                let map = arguments[0].as_map().borrow();
+                Ok(RuntimeValue::Number(map.entries.len() as f64))
+            }),
+            "keys" => BuiltinFunction::with_heap("keys", 1, |heap, arguments| {
+                let map = arguments[0].as_map().borrow();
+                let elements = map.entries.keys().map(RuntimeValue::from).collect();
+                Ok(RuntimeValue::List(heap.list(List { elements })))
+            }),
+            "values" => BuiltinFunction::with_heap("values", 1, |heap, arguments| {
+                let map = arguments[0].as_map().borrow();
+                let elements = map.entries.values().cloned().collect();
+                Ok(RuntimeValue::List(heap.list(List { elements })))
+            }),
+            "has" => BuiltinFunction::new("has", 2, |arguments| {
+                let map = arguments[0].as_map().borrow();
+                let key = MapKey::try_from(&arguments[1])?;
+                Ok(RuntimeValue::Bool(map.entries.contains_key(&key)))
+            }),
+            "remove" => BuiltinFunction::new("remove", 2, |arguments| {
+                let mut map = arguments[0].as_map().borrow_mut();
+                let key = MapKey::try_from(&arguments[1])?;
+                map.entries
+                    .shift_remove(&key)
+                    .ok_or_else(|| undefined_key(&arguments[1]))
+            }),
+            _ => {
+                return RuntimeError::new(format!("Undefined property '{}'.", name.lexeme), name)
+                    .into()
+            }
+        };
+
+        let method = method.bind(RuntimeValue::Map(map.clone()));
+        Ok(RuntimeValue::BuiltinFunction(heap.native(method)))
+    }
+
+    /// Removes all entries from this map and returns their values.
+    pub fn clear(&mut self) -> Vec<RuntimeValue> {
+        mem::take(&mut self.entries).into_values().collect()
+    }
+}
+
+fn undefined_key(key: &RuntimeValue) -> String {
+    match key {
+        RuntimeValue::String(key) => format!("Undefined key \"{}\".", key),
+        _ => format!("Undefined key {}.", key),
+    }
+}
+
+impl Trace for Map {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for value in self.entries.values() {
+            value.trace(visit);
+        }
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Formats the map like a map literal, with strings in quotes.
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (index, (key, value)) in self.entries.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            fmt_element(f, &RuntimeValue::from(key))?;
+            write!(f, ": ")?;
+            fmt_element(f, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// The namespace of an imported module, which holds the values of its
+/// exported declarations.
+pub struct Module {
+    name: String,
+    exports: HashMap<String, RuntimeValue>,
+}
+
+impl Module {
+    fn get(&self, name: &Token) -> Result<RuntimeValue, EarlyReturn> {
+        match self.exports.get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => RuntimeError::new(
+                format!("Module '{}' does not export '{}'.", self.name, name.lexeme),
+                name,
+            )
+            .into(),
+        }
+    }
+}
+
+impl Trace for Module {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for value in self.exports.values() {
+            value.trace(visit);
+        }
+    }
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+/// Returns the directory of the file at `path`.
+fn directory_of(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
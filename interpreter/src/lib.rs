@@ -0,0 +1,25 @@
+//! An implementation of the Lox language as a tree-walking interpreter.
+//!
+//! [Lox] is the entry point for running programs.
+
+mod ast;
+mod ast_printer;
+mod environment;
+mod gc;
+mod interpreter;
+mod lox;
+mod math;
+mod output;
+mod parser;
+mod resolver;
+mod scanner;
+mod strings;
+mod token;
+mod utils;
+
+pub use crate::{
+    interpreter::RuntimeValue,
+    lox::{Diagnostic, Location, Lox, LoxError, StackFrame},
+    output::{Output, WriteOutput},
+    token::Span,
+};
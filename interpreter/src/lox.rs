@@ -1,122 +1,328 @@
-use std::{
-    cell::RefCell,
-    env,
-    fs::File,
-    io::{self, Read, Write},
-    process::exit,
-    rc::Rc,
-};
+use std::{cell::RefCell, error::Error, fmt, fs, mem, path::Path, rc::Rc};
 
 use crate::{
-    interpreter::{Interpreter, RuntimeError},
+    ast::Stmt,
+    ast_printer::AstPrinter,
+    interpreter::{BuiltinFunction, Interpreter, RuntimeError, RuntimeValue},
+    output::{Output, WriteOutput},
     parser::Parser,
     resolver::Resolver,
     scanner::Scanner,
-    token::{Token, TokenType},
+    token::{Span, Token, TokenType},
 };
 
+/// How often the same frame is repeated at most in a printed stack trace.
+const MAX_REPEATED_FRAMES: usize = 3;
+
+/// A Lox engine, which runs programs in a persistent global environment.
+///
+/// ```
+/// use rust_lox::{Lox, RuntimeValue};
+///
+/// let mut lox = Lox::new();
+/// lox.define_native("double", 1, |arguments| match arguments[0] {
+///     RuntimeValue::Number(value) => Ok(RuntimeValue::Number(value * 2.0)),
+///     _ => Err("Argument must be a number.".to_string()),
+/// });
+///
+/// lox.run("var a = double(21);").unwrap();
+/// assert!(lox.run("double(nil);").is_err());
+/// ```
 pub struct Lox {
     error_collector: Rc<RefCell<ErrorCollector>>,
     interpreter: Interpreter,
 }
 
 impl Lox {
+    /// Creates an engine, which prints to stdout and reports errors to
+    /// stderr.
     pub fn new() -> Lox {
+        Lox::with_output(WriteOutput::standard())
+    }
+
+    /// Creates an engine, which prints and reports errors to `output`.
+    pub fn with_output(output: impl Output + 'static) -> Lox {
         let error_collector = Rc::new(RefCell::new(ErrorCollector::new()));
 
         Lox {
             error_collector: error_collector.clone(),
-            interpreter: Interpreter::new(error_collector.clone()),
+            interpreter: Interpreter::new(error_collector.clone(), Box::new(output)),
         }
     }
 
-    pub fn main(&mut self) {
-        let args = Vec::from_iter(env::args().skip(1));
+    /// Runs `source` as a program.
+    ///
+    /// Globals defined by the program stay defined for subsequent runs. The
+    /// program is not run at all if it contains any compile errors.
+    ///
+    /// Errors are reported to the output of the engine, in addition to being
+    /// returned.
+    pub fn run(&mut self, source: &str) -> Result<(), LoxError> {
+        let result = self.execute(source, false, None);
+        self.report(result)
+    }
 
-        match args.len() {
-            0 => self.run_prompt(),
-            1 => self.run_file(&args[0]),
-            _ => {
-                print!("Usage: lox [<file>]");
-                exit(1);
-            }
-        }
+    /// Runs the file at `path` like [Lox::run].
+    ///
+    /// Modules imported by the file are resolved relative to its directory,
+    /// while they are resolved relative to the working directory for other
+    /// programs.
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), LoxError> {
+        let path = path.as_ref();
+        let result = match fs::read_to_string(path) {
+            Ok(source) => self.execute(&source, false, Some(path)),
+            Err(error) => Err(LoxError::Io(format!(
+                "Could not read '{}': {}",
+                path.display(),
+                error
+            ))),
+        };
+        self.report(result)
     }
 
-    fn run_prompt(&mut self) {
-        let mut lines = io::stdin().lines();
+    /// Runs `source` like [Lox::run], but as input entered in an interactive
+    /// session: if the source ends with an expression without a semicolon,
+    /// the value of the expression is printed.
+    pub fn run_interactive(&mut self, source: &str) -> Result<(), LoxError> {
+        let result = self.execute(source, true, None);
+        self.report(result)
+    }
 
-        loop {
-            print!("> ");
-            io::stdout().flush().unwrap();
+    /// Parses `source` as a single expression and returns its syntax tree in
+    /// a parenthesized prefix notation, like `(+ 1 (* 2 3))`.
+    ///
+    /// Errors are reported like in [Lox::run].
+    pub fn syntax_tree(&mut self, source: &str) -> Result<String, LoxError> {
+        let mut error_collector = self.error_collector.borrow_mut();
+        let scanner = Scanner::new(&mut error_collector, source);
+        let tokens = scanner.scan_tokens();
+        let parser = Parser::new(&mut error_collector, tokens);
+        let expression = parser.parse_expression();
+        let result = error_collector.take_result();
+        drop(error_collector);
 
-            match lines.next() {
-                Some(line) => {
-                    self.run(&line.unwrap());
-                    self.error_collector.borrow_mut().reset();
-                }
-                None => {
-                    return;
+        self.report(result)?;
+        Ok(AstPrinter.print(&expression.unwrap()))
+    }
+
+    /// Returns the global variables, including native functions, sorted by
+    /// name.
+    pub fn globals(&self) -> Vec<(String, RuntimeValue)> {
+        self.interpreter.globals()
+    }
+
+    /// Frees the objects which are only kept alive by reference cycles and
+    /// returns how many objects have been freed.
+    ///
+    /// Such cycles are also collected automatically while a program runs, so
+    /// this is only needed to release memory at a specific point, like
+    /// between runs.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.interpreter.collect_garbage()
+    }
+
+    /// Reports the error in `result` to the output, if there is one.
+    fn report<T>(&mut self, result: Result<T, LoxError>) -> Result<T, LoxError> {
+        if let Err(error) = &result {
+            let output = self.interpreter.output();
+            match error {
+                LoxError::Compile(diagnostics) => {
+                    for diagnostic in diagnostics {
+                        output.error(&diagnostic.to_string());
+                    }
                 }
+                LoxError::Runtime(_) | LoxError::Io(_) => output.error(&error.to_string()),
             }
         }
+
+        result
     }
 
-    fn run_file(&mut self, path: &str) {
-        let mut file = File::open(path).expect("Could not open file to run.");
-        let mut content = String::new();
-        file.read_to_string(&mut content)
-            .expect("Could not read file to run.");
+    fn execute(
+        &mut self,
+        source: &str,
+        interactive: bool,
+        path: Option<&Path>,
+    ) -> Result<(), LoxError> {
+        let mut error_collector = self.error_collector.borrow_mut();
+        let statements = match compile(&mut error_collector, source, interactive) {
+            Some(statements) => statements,
+            None => return error_collector.take_result(),
+        };
+        drop(error_collector);
 
-        self.run(&content);
+        self.interpreter.interpret(&statements, path);
 
-        if self.error_collector.borrow().had_error {
-            exit(1);
-        }
-        if self.error_collector.borrow().had_runtime_error {
-            exit(1);
-        }
+        self.error_collector.borrow_mut().take_result()
     }
 
-    fn run(&mut self, source: &str) {
-        let mut error_collector = self.error_collector.borrow_mut();
-        let scanner = Scanner::new(&mut error_collector, source);
-        let tokens = scanner.scan_tokens();
-        let parser = Parser::new(&mut error_collector, tokens);
-        let statements = parser.parse();
+    /// Defines a global function, which calls `function` with its arguments.
+    ///
+    /// An error returned by `function` becomes a runtime error with that
+    /// message.
+    pub fn define_native<F>(&mut self, name: &str, arity: u8, function: F)
+    where
+        F: Fn(&[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        self.interpreter
+            .define_builtin(BuiltinFunction::new(name, arity, function));
+    }
+}
+
+/// Scans, parses and resolves `source` and returns its statements, unless it
+/// contains errors, which are reported to `error_collector` instead.
+pub(crate) fn compile(
+    error_collector: &mut ErrorCollector,
+    source: &str,
+    interactive: bool,
+) -> Option<Vec<Rc<Stmt>>> {
+    let scanner = Scanner::new(error_collector, source);
+    let tokens = scanner.scan_tokens();
+    let parser = Parser::new(error_collector, tokens);
+    let statements = match interactive {
+        true => parser.parse_interactive(),
+        false => parser.parse(),
+    };
+
+    if error_collector.had_error() {
+        return None;
+    }
 
-        if error_collector.had_error {
-            return;
+    let resolver = Resolver::new(error_collector);
+    resolver.resolve(&statements);
+
+    match error_collector.had_error() {
+        true => None,
+        false => Some(statements),
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Lox::new()
+    }
+}
+
+/// The reason a program could not be run to completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    /// The program contains errors, which were found before it was run.
+    Compile(Vec<Diagnostic>),
+    /// The program was aborted by an error while it was running.
+    Runtime(Box<Diagnostic>),
+    /// The file containing the program could not be read.
+    Io(String),
+}
+
+impl Error for LoxError {}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Compile(diagnostics) => {
+                for (index, diagnostic) in diagnostics.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            LoxError::Runtime(diagnostic) => {
+                write!(f, "{}\n{}", diagnostic.message, diagnostic.span.snippet())?;
+                // Deep recursion produces long runs of the same frame, of which
+                // only the first few are shown.
+                for frames in diagnostic.trace.chunk_by(|a, b| a == b) {
+                    for frame in frames.iter().take(MAX_REPEATED_FRAMES) {
+                        write!(f, "\n{}", frame)?;
+                    }
+                    if frames.len() > MAX_REPEATED_FRAMES {
+                        write!(
+                            f,
+                            "\n[previous frame repeated {} more times]",
+                            frames.len() - MAX_REPEATED_FRAMES
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            LoxError::Io(message) => f.write_str(message),
         }
+    }
+}
 
-        let resolver = Resolver::new(&mut error_collector);
-        resolver.resolve(&statements);
+/// An error in a program, with the place in the source where it occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+    pub location: Location,
+    /// The code which caused the error.
+    pub span: Span,
+    /// The calls which were in progress when a runtime error occurred,
+    /// innermost first. Compile errors have no trace.
+    pub trace: Vec<StackFrame>,
+}
 
-        if error_collector.had_error {
-            return;
+/// Formats the diagnostic as a compile error, followed by a snippet of the
+/// offending code.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error", self.line)?;
+        match &self.location {
+            Location::Line => {}
+            Location::Token(lexeme) => write!(f, " at '{}'", lexeme)?,
+            Location::End => write!(f, " at end")?,
         }
+        write!(f, ": {}\n{}", self.message, self.span.snippet())
+    }
+}
 
-        drop(error_collector);
+/// A call, which was in progress when a runtime error occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// The name of the called function, prefixed with the name of its class
+    /// for methods, or `None` for the top level script.
+    pub function: Option<String>,
+    /// The line which was being executed in the call.
+    pub line: usize,
+}
 
-        self.interpreter.interpret(&statements);
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {}()", self.line, function),
+            None => write!(f, "[line {}] in script", self.line),
+        }
     }
 }
 
+/// Where on its line a [Diagnostic] occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// Somewhere on the line, but not at a specific token.
+    Line,
+    /// At the token with the given lexeme.
+    Token(String),
+    /// At the end of the source.
+    End,
+}
+
 pub struct ErrorCollector {
-    had_error: bool,
-    had_runtime_error: bool,
+    errors: Vec<Diagnostic>,
+    runtime_error: Option<Diagnostic>,
 }
 
 impl ErrorCollector {
     fn new() -> ErrorCollector {
         ErrorCollector {
-            had_error: false,
-            had_runtime_error: false,
+            errors: vec![],
+            runtime_error: None,
         }
     }
 
-    pub fn scanner_error(&mut self, line: usize, message: &str) {
-        self.report_static_error(line, "", message);
+    pub fn scanner_error(&mut self, line: usize, span: Span, message: &str) {
+        self.report_static_error(line, Location::Line, span, message);
     }
 
     pub fn parser_error(&mut self, token: &Token, message: &str) {
@@ -128,25 +334,52 @@ impl ErrorCollector {
     }
 
     pub fn runtime_error(&mut self, err: RuntimeError) {
-        println!("{} [line {}]", err.message, err.token.line);
-        self.had_runtime_error = true;
+        self.runtime_error = Some(Diagnostic {
+            message: err.message,
+            line: err.token.line,
+            location: location_of(&err.token),
+            span: err.span,
+            trace: err.trace,
+        });
     }
 
-    fn reset(&mut self) {
-        self.had_error = false;
-        self.had_runtime_error = false;
+    fn had_error(&self) -> bool {
+        !self.errors.is_empty()
     }
 
-    fn report_static_error_for_token(&mut self, token: &Token, message: &str) {
-        if token.token_type == TokenType::Eof {
-            self.report_static_error(token.line, " at end", message);
-        } else {
-            self.report_static_error(token.line, &format!(" at '{}'", token.lexeme), message);
+    /// Returns the errors reported since the last call and forgets them.
+    fn take_result(&mut self) -> Result<(), LoxError> {
+        // Compile errors in an imported module also cause a runtime error in
+        // the importing program, which is not worth reporting.
+        let runtime_error = self.runtime_error.take();
+        if self.had_error() {
+            return Err(LoxError::Compile(mem::take(&mut self.errors)));
         }
+
+        match runtime_error {
+            Some(error) => Err(LoxError::Runtime(Box::new(error))),
+            None => Ok(()),
+        }
+    }
+
+    fn report_static_error_for_token(&mut self, token: &Token, message: &str) {
+        self.report_static_error(token.line, location_of(token), token.span.clone(), message);
+    }
+
+    fn report_static_error(&mut self, line: usize, location: Location, span: Span, message: &str) {
+        self.errors.push(Diagnostic {
+            message: message.to_string(),
+            line,
+            location,
+            span,
+            trace: vec![],
+        });
     }
+}
 
-    fn report_static_error(&mut self, line: usize, at: &str, message: &str) {
-        println!("[line {}] Error{}: {}", line, at, message);
-        self.had_error = true;
+fn location_of(token: &Token) -> Location {
+    match token.token_type {
+        TokenType::Eof => Location::End,
+        _ => Location::Token(token.lexeme.clone()),
     }
 }
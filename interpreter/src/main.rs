@@ -1,13 +1,85 @@
-mod ast;
-mod environment;
-mod interpreter;
-mod lox;
-mod parser;
-mod resolver;
-mod scanner;
-mod token;
-mod utils;
+use std::{env, process::exit, time::Instant};
+
+use lox_repl::Repl;
+use rust_lox::{Lox, LoxError};
 
 fn main() {
-    lox::Lox::new().main();
+    let args = Vec::from_iter(env::args().skip(1));
+    let mut lox = Lox::new();
+
+    match args.len() {
+        0 => run_prompt(&mut lox),
+        1 => run_file(&mut lox, &args[0]),
+        _ => {
+            print!("Usage: lox [<file>]");
+            exit(1);
+        }
+    }
+}
+
+fn run_prompt(lox: &mut Lox) {
+    let mut repl = Repl::new(".rust_lox_history");
+
+    while let Some(input) = repl.read() {
+        match input.strip_prefix(':') {
+            Some(command) => run_command(lox, command),
+            None => {
+                // Errors have already been reported by the engine.
+                let _ = lox.run_interactive(&input);
+            }
+        }
+    }
+}
+
+const HELP: &str = "\
+:env          List the global variables.
+:load <file>  Run a file in the current session.
+:reset        Forget all global variables.
+:ast <expr>   Show the syntax tree of an expression.
+:time <code>  Run code and show how long it took.
+:help         Show this help.";
+
+/// Runs a REPL meta-command, like `load file.lox`.
+fn run_command(lox: &mut Lox, command: &str) {
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "env" => {
+            for (name, value) in lox.globals() {
+                println!("{} = {}", name, value);
+            }
+        }
+        "load" => {
+            let _ = lox.run_file(argument);
+        }
+        "reset" => *lox = Lox::new(),
+        "ast" => {
+            if let Ok(tree) = lox.syntax_tree(argument) {
+                println!("{}", tree);
+            }
+        }
+        "time" => {
+            let start = Instant::now();
+            let _ = lox.run_interactive(argument);
+            println!("Took {:?}.", start.elapsed());
+        }
+        "help" => println!("{}", HELP),
+        _ => eprintln!(
+            "Unknown command ':{}'. Enter :help for a list of commands.",
+            name
+        ),
+    }
+}
+
+fn run_file(lox: &mut Lox, path: &str) {
+    if let Err(error) = lox.run_file(path) {
+        match error {
+            LoxError::Compile(_) => exit(65),
+            LoxError::Runtime(_) => exit(70),
+            LoxError::Io(_) => exit(66),
+        }
+    }
 }
@@ -0,0 +1,128 @@
+//! Native functions for working with numbers.
+//!
+//! `random` returns the same sequence of numbers in the interpreter and in the
+//! VM after both have been seeded with `seedRandom`.
+
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::interpreter::{BuiltinFunction, RuntimeValue};
+
+/// The largest number of decimals `toFixed` accepts.
+const MAX_DECIMALS: i64 = 100;
+
+/// Returns the math functions, which are defined as globals.
+pub fn functions() -> Vec<BuiltinFunction> {
+    let random = Rc::new(Random::new(time_seed()));
+    let seeded = random.clone();
+
+    vec![
+        unary("floor", f64::floor),
+        unary("ceil", f64::ceil),
+        unary("round", f64::round),
+        unary("abs", f64::abs),
+        unary("sqrt", f64::sqrt),
+        unary("sin", f64::sin),
+        unary("cos", f64::cos),
+        unary("log", f64::ln),
+        binary("pow", f64::powf),
+        binary("min", f64::min),
+        binary("max", f64::max),
+        BuiltinFunction::new("random", 0, move |_| {
+            Ok(RuntimeValue::Number(random.next()))
+        }),
+        BuiltinFunction::new("seedRandom", 1, move |arguments| {
+            seeded.seed(number_argument(&arguments[0])?.to_bits());
+            Ok(RuntimeValue::Nil)
+        }),
+        BuiltinFunction::new("parseNumber", 1, |arguments| {
+            let string = match &arguments[0] {
+                RuntimeValue::String(string) => string,
+                _ => return Err("Argument must be a string.".to_string()),
+            };
+            Ok(match string.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => RuntimeValue::Number(value),
+                _ => RuntimeValue::Nil,
+            })
+        }),
+        BuiltinFunction::new("toFixed", 2, |arguments| {
+            let value = number_argument(&arguments[0])?;
+            let decimals = match number_argument(&arguments[1])? {
+                decimals if decimals.fract() == 0.0 => decimals as i64,
+                _ => return Err("Argument must be an integer.".to_string()),
+            };
+            if !(0..=MAX_DECIMALS).contains(&decimals) {
+                return Err(format!(
+                    "Number of decimals must be between 0 and {}.",
+                    MAX_DECIMALS
+                ));
+            }
+
+            let string = format!("{:.*}", decimals as usize, value);
+            Ok(RuntimeValue::String(Rc::new(string)))
+        }),
+    ]
+}
+
+fn unary(name: &str, function: fn(f64) -> f64) -> BuiltinFunction {
+    BuiltinFunction::new(name, 1, move |arguments| {
+        Ok(RuntimeValue::Number(function(number_argument(
+            &arguments[0],
+        )?)))
+    })
+}
+
+fn binary(name: &str, function: fn(f64, f64) -> f64) -> BuiltinFunction {
+    BuiltinFunction::new(name, 2, move |arguments| {
+        let left = number_argument(&arguments[0])?;
+        let right = number_argument(&arguments[1])?;
+        Ok(RuntimeValue::Number(function(left, right)))
+    })
+}
+
+fn number_argument(value: &RuntimeValue) -> Result<f64, String> {
+    match *value {
+        RuntimeValue::Number(value) => Ok(value),
+        _ => Err("Argument must be a number.".to_string()),
+    }
+}
+
+/// A SplitMix64 generator, which is small and good enough for scripts.
+struct Random {
+    state: Cell<u64>,
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            state: Cell::new(seed),
+        }
+    }
+
+    fn seed(&self, seed: u64) {
+        self.state.set(seed);
+    }
+
+    /// Returns a number in the range `0..1`.
+    fn next(&self) -> f64 {
+        let state = self.state.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.state.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        // The top 53 bits fill the mantissa of a double exactly.
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
@@ -1,13 +1,15 @@
-use std::{error::Error, fmt, rc::Rc};
+use std::{error::Error, fmt, path::Path, rc::Rc};
 
 use crate::{
     ast::{
-        AssignExpr, BinaryExpr, BlockStmt, CallExpr, ClassStmt, ConditionExpr, Expr,
-        ExpressionStmt, FunctionStmt, GetExpr, GroupingExpr, IfStmt, LiteralExpr, PrintStmt,
-        ReturnStmt, SetExpr, Stmt, SuperExpr, ThisExpr, UnaryExpr, VarStmt, VariableExpr,
-        WhileStmt,
+        AssignExpr, BinaryExpr, BlockStmt, BreakStmt, CallExpr, CatchClause, ClassStmt,
+        ConditionExpr, ContinueStmt, ExportStmt, Expr, ExpressionStmt, FunctionStmt, GetExpr,
+        GroupingExpr, IfStmt, ImportStmt, IndexExpr, ListExpr, LiteralExpr, MapEntry, MapExpr,
+        PrintStmt, ReturnStmt, SetExpr, SetIndexExpr, Stmt, SuperExpr, ThisExpr, ThrowStmt,
+        TryStmt, UnaryExpr, VarStmt, VariableExpr, WhileStmt,
     },
     lox::ErrorCollector,
+    scanner,
     token::{LiteralValue, Token, TokenType},
     utils::Late,
 };
@@ -16,17 +18,44 @@ pub struct Parser<'a> {
     error_collector: &'a mut ErrorCollector,
     tokens: Vec<Token>,
     current: usize,
+    /// Whether an expression at the end of the input may omit its semicolon,
+    /// in which case its value is printed.
+    interactive: bool,
+    /// The number of blocks, which are being parsed.
+    block_depth: usize,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(error_collector: &'a mut ErrorCollector, tokens: Vec<Token>) -> Parser {
+    pub fn new(error_collector: &'a mut ErrorCollector, tokens: Vec<Token>) -> Parser<'a> {
         Parser {
             error_collector,
             tokens,
             current: 0,
+            interactive: false,
+            block_depth: 0,
         }
     }
 
+    /// Parses the input like [Parser::parse], but parses an expression at the
+    /// end of the input, which is not terminated by a semicolon, as a print
+    /// statement.
+    pub fn parse_interactive(mut self) -> Vec<Rc<Stmt>> {
+        self.interactive = true;
+        self.parse()
+    }
+
+    /// Parses the input as a single expression.
+    pub fn parse_expression(mut self) -> Option<Rc<Expr>> {
+        let expression = self.expression().ok()?;
+
+        if !self.is_at_end() {
+            let token = self.peek().clone();
+            return self.error(&token, "Expect end of expression.").ok();
+        }
+
+        Some(expression)
+    }
+
     pub fn parse(mut self) -> Vec<Rc<Stmt>> {
         let mut statements = Vec::new();
 
@@ -49,7 +78,16 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Skips tokens until a statement boundary, so that errors in the
+    /// following statements can be reported independently of the last one.
+    ///
+    /// The closing brace of the enclosing block is not skipped, so that the
+    /// block still ends where it should.
     fn synchronize(&mut self) {
+        let in_block = self.block_depth > 0;
+        if in_block && self.peek().token_type == TokenType::RightBrace {
+            return;
+        }
         self.advance();
 
         loop {
@@ -57,23 +95,33 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            if self.match_token(TokenType::Semicolon) {
+            if self.previous().token_type == TokenType::Semicolon {
                 break;
             }
 
+            // Keywords which start a declaration or statement. The compiler of
+            // the VM synchronizes on the same ones, except for those of
+            // statements it doesn't support.
             use TokenType::*;
-            if let Var | Fun | Class | This | Super | If | For | While | Return =
-                self.peek().token_type
+            if let Class | Fun | Var | For | If | While | Print | Return | Break | Continue
+            | Throw | Try | Import | Export = self.peek().token_type
             {
                 break;
             }
+            if in_block && self.peek().token_type == TokenType::RightBrace {
+                break;
+            }
 
             self.advance();
         }
     }
 
     fn declaration(&mut self) -> Result<Rc<Stmt>, ParserError> {
-        if self.match_token(TokenType::Fun) {
+        if self.match_token(TokenType::Import) {
+            self.import_declaration()
+        } else if self.match_token(TokenType::Export) {
+            self.export_declaration()
+        } else if self.match_token(TokenType::Fun) {
             self.function_declaration("function")
         } else if self.match_token(TokenType::Class) {
             self.class_declaration()
@@ -84,6 +132,72 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn import_declaration(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        let keyword = self.previous();
+
+        let name = if self.match_token(TokenType::Identifier) {
+            let name = self.previous();
+            // `from` is only a keyword in this position.
+            if self.peek().lexeme != "from" {
+                let token = self.peek().clone();
+                return self.error(&token, "Expect 'from' after import name.");
+            }
+            self.advance();
+            Some(name.lexeme)
+        } else {
+            None
+        };
+
+        let path_token = self.consume(TokenType::String, "Expect module path.")?;
+        let path = match &path_token.literal {
+            Some(LiteralValue::String(path)) => path.clone(),
+            _ => unreachable!(),
+        };
+
+        // Without a name, the module is bound to the name of its file.
+        let name = match name {
+            Some(name) => name,
+            None => match module_name(&path) {
+                Some(name) => name,
+                None => {
+                    return self.error(
+                        &path_token,
+                        "Module file name must be a valid identifier. Use 'import <name> from' instead.",
+                    )
+                }
+            },
+        };
+
+        let semicolon = self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+
+        Ok(Rc::new(Stmt::Import(ImportStmt {
+            span: keyword.span.to(&semicolon.span),
+            keyword,
+            name,
+            path,
+        })))
+    }
+
+    fn export_declaration(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        let keyword = self.previous();
+
+        let declaration = if self.match_token(TokenType::Fun) {
+            self.function_declaration("function")?
+        } else if self.match_token(TokenType::Class) {
+            self.class_declaration()?
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration()?
+        } else {
+            let token = self.peek().clone();
+            return self.error(&token, "Expect declaration after 'export'.");
+        };
+
+        Ok(Rc::new(Stmt::Export(ExportStmt {
+            keyword,
+            declaration,
+        })))
+    }
+
     fn function_declaration(&mut self, kind: &str) -> Result<Rc<Stmt>, ParserError> {
         let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
 
@@ -178,6 +292,14 @@ impl<'a> Parser<'a> {
             self.for_stmt()
         } else if self.match_token(TokenType::Return) {
             self.return_stmt()
+        } else if self.match_token(TokenType::Break) {
+            self.break_stmt()
+        } else if self.match_token(TokenType::Continue) {
+            self.continue_stmt()
+        } else if self.match_token(TokenType::Throw) {
+            self.throw_stmt()
+        } else if self.match_token(TokenType::Try) {
+            self.try_stmt()
         } else {
             self.expression_stmt()
         }
@@ -186,9 +308,13 @@ impl<'a> Parser<'a> {
     fn block(&mut self) -> Result<Vec<Rc<Stmt>>, ParserError> {
         let mut statements = Vec::new();
 
+        self.block_depth += 1;
         while self.peek().token_type != TokenType::RightBrace && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.try_declaration() {
+                statements.push(statement);
+            }
         }
+        self.block_depth -= 1;
 
         self.consume(TokenType::RightBrace, "Expect '}' after statement block.")?;
 
@@ -233,7 +359,11 @@ impl<'a> Parser<'a> {
 
         let body = self.statement()?;
 
-        Ok(Rc::new(Stmt::While(WhileStmt { condition, body })))
+        Ok(Rc::new(Stmt::While(WhileStmt {
+            condition,
+            body,
+            increment: None,
+        })))
     }
 
     fn for_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
@@ -250,6 +380,7 @@ impl<'a> Parser<'a> {
         let condition = if self.match_token(TokenType::Semicolon) {
             Rc::new(Expr::Literal(LiteralExpr {
                 value: LiteralValue::Bool(true),
+                span: self.previous().span,
             }))
         } else {
             let expr = self.expression()?;
@@ -265,18 +396,13 @@ impl<'a> Parser<'a> {
             expr
         };
 
-        let mut body = self.statement()?;
-
-        if let Some(expression) = increment {
-            body = Rc::new(Stmt::Block(BlockStmt {
-                statements: vec![
-                    body,
-                    Rc::new(Stmt::Expression(ExpressionStmt { expression })),
-                ],
-            }))
-        };
+        let body = self.statement()?;
 
-        body = Rc::new(Stmt::While(WhileStmt { condition, body }));
+        let mut body = Rc::new(Stmt::While(WhileStmt {
+            condition,
+            body,
+            increment,
+        }));
 
         if let Some(statement) = initializer {
             body = Rc::new(Stmt::Block(BlockStmt {
@@ -301,9 +427,68 @@ impl<'a> Parser<'a> {
         Ok(Rc::new(Stmt::Return(ReturnStmt { token, value })))
     }
 
+    fn break_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        let token = self.previous();
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+        Ok(Rc::new(Stmt::Break(BreakStmt { token })))
+    }
+
+    fn continue_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        let token = self.previous();
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+        Ok(Rc::new(Stmt::Continue(ContinueStmt { token })))
+    }
+
+    fn throw_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        let token = self.previous();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Rc::new(Stmt::Throw(ThrowStmt { token, value })))
+    }
+
+    fn try_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let catch = if self.match_token(TokenType::Catch) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(TokenType::Identifier, "Expect catch variable name.")?;
+            self.consume(TokenType::RightParen, "Expect ')' after catch variable.")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some(CatchClause {
+                name,
+                body: self.block()?,
+            })
+        } else {
+            None
+        };
+
+        let finally = if self.match_token(TokenType::Finally) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            let token = self.peek().clone();
+            return self.error(&token, "Expect 'catch' or 'finally' after try block.");
+        }
+
+        Ok(Rc::new(Stmt::Try(TryStmt {
+            body,
+            catch,
+            finally,
+        })))
+    }
+
     fn expression_stmt(&mut self) -> Result<Rc<Stmt>, ParserError> {
         let expression = self.expression()?;
 
+        if self.interactive && self.is_at_end() {
+            return Ok(Rc::new(Stmt::Print(PrintStmt { expression })));
+        }
+
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after expression statement.",
@@ -320,6 +505,7 @@ impl<'a> Parser<'a> {
         let expr = self.or_expr()?;
 
         if self.match_token(TokenType::Equal) {
+            let equals = self.previous();
             let value = self.assign_expr()?;
 
             match expr.as_ref() {
@@ -333,11 +519,17 @@ impl<'a> Parser<'a> {
                     name: expr.name.clone(),
                     value,
                 }))),
+                Expr::Index(expr) => Ok(Rc::new(Expr::SetIndex(SetIndexExpr {
+                    object: expr.object.clone(),
+                    bracket: expr.bracket.clone(),
+                    index: expr.index.clone(),
+                    value,
+                }))),
                 _ => {
-                    return self.error(
-                        &self.peek().clone(),
-                        "Expect assignment to variable or property.",
-                    );
+                    // The parser is not confused, so there is no need to
+                    // synchronize.
+                    let _ = self.error::<()>(&equals, "Invalid assignment target.");
+                    Ok(expr)
                 }
             }
         } else {
@@ -432,7 +624,10 @@ impl<'a> Parser<'a> {
     fn factor_expr(&mut self) -> Result<Rc<Expr>, ParserError> {
         let mut expr = self.unary_expr()?;
 
-        while self.match_token(TokenType::Slash) || self.match_token(TokenType::Star) {
+        while self.match_token(TokenType::Slash)
+            || self.match_token(TokenType::Star)
+            || self.match_token(TokenType::Percent)
+        {
             let operator = self.previous();
             let right = self.unary_expr()?;
             expr = Rc::new(Expr::Binary(BinaryExpr {
@@ -453,19 +648,6 @@ impl<'a> Parser<'a> {
                 operator,
                 expression,
             })))
-        } else {
-            self.grouping_expr()
-        }
-    }
-
-    fn grouping_expr(&mut self) -> Result<Rc<Expr>, ParserError> {
-        if self.match_token(TokenType::LeftParen) {
-            let expression = self.expression()?;
-            self.consume(
-                TokenType::RightParen,
-                "Expect ')' after grouping expression",
-            )?;
-            Ok(Rc::new(Expr::Grouping(GroupingExpr { expression })))
         } else {
             self.call_expr()
         }
@@ -484,6 +666,14 @@ impl<'a> Parser<'a> {
                     object: expression,
                     name,
                 }))
+            } else if self.match_token(TokenType::LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expression = Rc::new(Expr::Index(IndexExpr {
+                    object: expression,
+                    bracket,
+                    index,
+                }))
             } else {
                 break;
             }
@@ -507,7 +697,7 @@ impl<'a> Parser<'a> {
 
             arguments.push(self.expression()?);
 
-            if self.match_token(TokenType::Comma) {
+            if !self.match_token(TokenType::Comma) {
                 break;
             }
         }
@@ -525,24 +715,57 @@ impl<'a> Parser<'a> {
         if self.match_token(TokenType::Nil) {
             Ok(Rc::new(Expr::Literal(LiteralExpr {
                 value: LiteralValue::Nil,
+                span: self.previous().span,
             })))
         } else if self.match_token(TokenType::True) {
             Ok(Rc::new(Expr::Literal(LiteralExpr {
                 value: LiteralValue::Bool(true),
+                span: self.previous().span,
             })))
         } else if self.match_token(TokenType::False) {
             Ok(Rc::new(Expr::Literal(LiteralExpr {
                 value: LiteralValue::Bool(false),
+                span: self.previous().span,
             })))
         } else if self.match_token(TokenType::Number) || self.match_token(TokenType::String) {
+            let token = self.previous();
             Ok(Rc::new(Expr::Literal(LiteralExpr {
-                value: self.previous().literal.unwrap(),
+                value: token.literal.unwrap(),
+                span: token.span,
             })))
         } else if self.match_token(TokenType::Identifier) {
             Ok(Rc::new(Expr::Variable(VariableExpr {
                 name: self.previous(),
                 scope_index: Late::new(),
             })))
+        } else if self.match_token(TokenType::LeftParen) {
+            let left_paren = self.previous();
+            let expression = self.expression()?;
+            let right_paren =
+                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            Ok(Rc::new(Expr::Grouping(GroupingExpr {
+                expression,
+                span: left_paren.span.to(&right_paren.span),
+            })))
+        } else if self.match_token(TokenType::LeftBracket) {
+            let left_bracket = self.previous();
+            let elements = self.list_elements()?;
+            let right_bracket =
+                self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+            Ok(Rc::new(Expr::List(ListExpr {
+                elements,
+                span: left_bracket.span.to(&right_bracket.span),
+            })))
+        } else if self.match_token(TokenType::LeftBrace) {
+            let left_brace = self.previous();
+            let entries = self.map_entries()?;
+            let right_brace =
+                self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+            Ok(Rc::new(Expr::Map(MapExpr {
+                span: left_brace.span.to(&right_brace.span),
+                brace: left_brace,
+                entries,
+            })))
         } else if self.match_token(TokenType::This) {
             Ok(Rc::new(Expr::This(ThisExpr {
                 token: self.previous(),
@@ -561,7 +784,42 @@ impl<'a> Parser<'a> {
                 scope_index: Late::new(),
             })))
         } else {
-            self.error(&self.peek().clone(), "Expected expression.")
+            self.error(&self.peek().clone(), "Expect expression.")
+        }
+    }
+
+    fn list_elements(&mut self) -> Result<Vec<Rc<Expr>>, ParserError> {
+        let mut elements = vec![];
+
+        if self.peek().token_type == TokenType::RightBracket {
+            return Ok(elements);
+        }
+
+        loop {
+            elements.push(self.expression()?);
+
+            if !self.match_token(TokenType::Comma) {
+                return Ok(elements);
+            }
+        }
+    }
+
+    fn map_entries(&mut self) -> Result<Vec<MapEntry>, ParserError> {
+        let mut entries = vec![];
+
+        if self.peek().token_type == TokenType::RightBrace {
+            return Ok(entries);
+        }
+
+        loop {
+            let key = self.expression()?;
+            self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+
+            if !self.match_token(TokenType::Comma) {
+                return Ok(entries);
+            }
         }
     }
 
@@ -569,7 +827,7 @@ impl<'a> Parser<'a> {
         self.peek().token_type == TokenType::Eof
     }
 
-    fn peek<'b>(&'b self) -> &'b Token {
+    fn peek(&self) -> &Token {
         &self.tokens[self.current]
     }
 
@@ -578,7 +836,9 @@ impl<'a> Parser<'a> {
     }
 
     fn advance(&mut self) {
-        self.current += 1;
+        if !self.is_at_end() {
+            self.current += 1;
+        }
     }
 
     fn match_token(&mut self, token_type: TokenType) -> bool {
@@ -606,6 +866,12 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Returns the stem of the file name in `path`, if it is a valid identifier.
+fn module_name(path: &str) -> Option<String> {
+    let name = Path::new(path).file_stem()?.to_str()?;
+    scanner::is_identifier(name).then(|| name.to_string())
+}
+
 #[derive(Debug)]
 struct ParserError {}
 
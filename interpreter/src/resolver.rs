@@ -1,11 +1,12 @@
-use std::{borrow::BorrowMut, collections::HashMap, rc::Rc};
+use std::{borrow::BorrowMut, collections::HashMap, mem, rc::Rc};
 
 use crate::{
     ast::{
-        AssignExpr, BinaryExpr, BlockStmt, CallExpr, ClassStmt, ConditionExpr, Expr, ExprVisitor,
-        ExpressionStmt, FunctionStmt, GetExpr, GroupingExpr, IfStmt, LiteralExpr, PrintStmt,
-        ReturnStmt, SetExpr, Stmt, StmtVisitor, SuperExpr, ThisExpr, UnaryExpr, VarStmt,
-        VariableExpr, VisitExpr, VisitStmt, WhileStmt,
+        AssignExpr, BinaryExpr, BlockStmt, BreakStmt, CallExpr, ClassStmt, ConditionExpr,
+        ContinueStmt, ExportStmt, Expr, ExprVisitor, ExpressionStmt, FunctionStmt, GetExpr,
+        GroupingExpr, IfStmt, ImportStmt, IndexExpr, ListExpr, LiteralExpr, MapExpr, PrintStmt,
+        ReturnStmt, SetExpr, SetIndexExpr, Stmt, StmtVisitor, SuperExpr, ThisExpr, ThrowStmt,
+        TryStmt, UnaryExpr, VarStmt, VariableExpr, VisitExpr, VisitStmt, WhileStmt,
     },
     lox::ErrorCollector,
     token::Token,
@@ -31,6 +32,8 @@ pub struct Resolver<'a> {
     scopes: Vec<HashMap<String, bool>>,
     function_type: FunctionType,
     class_type: ClassType,
+    /// Number of loops around the current statement in the current function.
+    loop_depth: usize,
 }
 
 impl<'a> Resolver<'a> {
@@ -40,6 +43,7 @@ impl<'a> Resolver<'a> {
             scopes: vec![],
             function_type: FunctionType::None,
             class_type: ClassType::None,
+            loop_depth: 0,
         }
     }
 
@@ -91,6 +95,8 @@ impl<'a> Resolver<'a> {
     fn resolve_function(&mut self, stmt: &FunctionStmt, function_type: FunctionType) {
         let outer_function_type = self.function_type;
         self.function_type = function_type;
+        // Loops around the declaration can't be exited from inside of it.
+        let outer_loop_depth = mem::take(&mut self.loop_depth);
 
         self.begin_scope();
 
@@ -104,6 +110,7 @@ impl<'a> Resolver<'a> {
         self.end_scope();
 
         self.function_type = outer_function_type;
+        self.loop_depth = outer_loop_depth;
     }
 
     fn resolve_local_scope_index(&mut self, name: &Token) -> Option<usize> {
@@ -118,17 +125,17 @@ impl<'a> Resolver<'a> {
 }
 
 impl<'a> StmtVisitor<()> for Resolver<'a> {
-    fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt, _: &Rc<Stmt>) -> () {
+    fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt, _: &Rc<Stmt>) {
         self.resolve_expr(&stmt.expression);
     }
 
-    fn visit_block_stmt(&mut self, stmt: &BlockStmt, _: &Rc<Stmt>) -> () {
+    fn visit_block_stmt(&mut self, stmt: &BlockStmt, _: &Rc<Stmt>) {
         self.begin_scope();
         self.resolve_stmt_vec(&stmt.statements);
         self.end_scope();
     }
 
-    fn visit_var_stmt(&mut self, stmt: &VarStmt, _: &Rc<Stmt>) -> () {
+    fn visit_var_stmt(&mut self, stmt: &VarStmt, _: &Rc<Stmt>) {
         self.declare(&stmt.name);
 
         if let Some(initializer) = &stmt.initializer {
@@ -138,13 +145,13 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         self.define(&stmt.name);
     }
 
-    fn visit_function_stmt(&mut self, stmt: &FunctionStmt, _: &Rc<Stmt>) -> () {
+    fn visit_function_stmt(&mut self, stmt: &FunctionStmt, _: &Rc<Stmt>) {
         self.declare(&stmt.name);
         self.define(&stmt.name);
         self.resolve_function(stmt, FunctionType::Function);
     }
 
-    fn visit_class_stmt(&mut self, stmt: &ClassStmt, _: &Rc<Stmt>) -> () {
+    fn visit_class_stmt(&mut self, stmt: &ClassStmt, _: &Rc<Stmt>) {
         let outer_class_type = self.class_type;
         self.class_type = ClassType::Class;
 
@@ -155,7 +162,7 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
             self.class_type = ClassType::SubClass;
 
             let super_class = &super_class_ptr.as_variable();
-            if &stmt.name.lexeme == &super_class.name.lexeme {
+            if stmt.name.lexeme == super_class.name.lexeme {
                 self.error_collector
                     .resolver_error(&super_class.name, "Class cannot extend itself.");
             }
@@ -187,18 +194,18 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
             self.end_scope();
         }
 
-        if let Some(_) = &stmt.super_class {
+        if stmt.super_class.is_some() {
             self.end_scope();
         }
 
         self.class_type = outer_class_type;
     }
 
-    fn visit_print_stmt(&mut self, stmt: &PrintStmt, _: &Rc<Stmt>) -> () {
+    fn visit_print_stmt(&mut self, stmt: &PrintStmt, _: &Rc<Stmt>) {
         self.resolve_expr(&stmt.expression);
     }
 
-    fn visit_if_stmt(&mut self, stmt: &IfStmt, _: &Rc<Stmt>) -> () {
+    fn visit_if_stmt(&mut self, stmt: &IfStmt, _: &Rc<Stmt>) {
         self.resolve_expr(&stmt.condition);
         self.resolve_stmt(&stmt.then_statement);
         if let Some(else_statement) = &stmt.else_statement {
@@ -206,22 +213,24 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         }
     }
 
-    fn visit_while_stmt(&mut self, stmt: &WhileStmt, _: &Rc<Stmt>) -> () {
+    fn visit_while_stmt(&mut self, stmt: &WhileStmt, _: &Rc<Stmt>) {
         self.resolve_expr(&stmt.condition);
+        self.loop_depth += 1;
         self.resolve_stmt(&stmt.body);
+        self.loop_depth -= 1;
+        if let Some(increment) = &stmt.increment {
+            self.resolve_expr(increment);
+        }
     }
 
-    fn visit_return_stmt(&mut self, stmt: &ReturnStmt, _: &Rc<Stmt>) -> () {
+    fn visit_return_stmt(&mut self, stmt: &ReturnStmt, _: &Rc<Stmt>) {
         match self.function_type {
             FunctionType::None => self
                 .error_collector
                 .resolver_error(&stmt.token, "Can't return from top level code."),
-            FunctionType::Initialize => {
-                if let Some(_) = stmt.value {
-                    self.error_collector
-                        .resolver_error(&stmt.token, "Can't return value from initializer.");
-                }
-            }
+            FunctionType::Initialize if stmt.value.is_some() => self
+                .error_collector
+                .resolver_error(&stmt.token, "Can't return value from initializer."),
             _ => {}
         };
 
@@ -229,12 +238,67 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
             self.resolve_expr(value);
         }
     }
+
+    fn visit_break_stmt(&mut self, stmt: &BreakStmt, _: &Rc<Stmt>) {
+        if self.loop_depth == 0 {
+            self.error_collector
+                .resolver_error(&stmt.token, "Can't use 'break' outside of a loop.");
+        }
+    }
+
+    fn visit_continue_stmt(&mut self, stmt: &ContinueStmt, _: &Rc<Stmt>) {
+        if self.loop_depth == 0 {
+            self.error_collector
+                .resolver_error(&stmt.token, "Can't use 'continue' outside of a loop.");
+        }
+    }
+
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt, _: &Rc<Stmt>) {
+        if !self.scopes.is_empty() {
+            self.error_collector
+                .resolver_error(&stmt.keyword, "Can only import at the top level.");
+        }
+    }
+
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt, _: &Rc<Stmt>) {
+        if !self.scopes.is_empty() {
+            self.error_collector
+                .resolver_error(&stmt.keyword, "Can only export top level declarations.");
+        }
+
+        self.resolve_stmt(&stmt.declaration);
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt, _: &Rc<Stmt>) {
+        self.resolve_expr(&stmt.value);
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt, _: &Rc<Stmt>) {
+        self.begin_scope();
+        self.resolve_stmt_vec(&stmt.body);
+        self.end_scope();
+
+        if let Some(catch) = &stmt.catch {
+            // The catch variable is declared in the scope of the catch body.
+            self.begin_scope();
+            self.declare(&catch.name);
+            self.define(&catch.name);
+            self.resolve_stmt_vec(&catch.body);
+            self.end_scope();
+        }
+
+        if let Some(finally) = &stmt.finally {
+            self.begin_scope();
+            self.resolve_stmt_vec(finally);
+            self.end_scope();
+        }
+    }
 }
 
 impl<'a> ExprVisitor<()> for Resolver<'a> {
-    fn visit_literal_expr(&mut self, _: &LiteralExpr, _: &Rc<Expr>) -> () {}
+    fn visit_literal_expr(&mut self, _: &LiteralExpr, _: &Rc<Expr>) {}
 
-    fn visit_variable_expr(&mut self, expr: &VariableExpr, _: &Rc<Expr>) -> () {
+    fn visit_variable_expr(&mut self, expr: &VariableExpr, _: &Rc<Expr>) {
         if let Some(scope) = self.scopes.last() {
             if let Some(defined) = scope.get(&expr.name.lexeme) {
                 if !defined {
@@ -250,31 +314,31 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
             .set(self.resolve_local_scope_index(&expr.name));
     }
 
-    fn visit_assign_expr(&mut self, expr: &AssignExpr, _: &Rc<Expr>) -> () {
+    fn visit_assign_expr(&mut self, expr: &AssignExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.value);
         expr.scope_index
             .set(self.resolve_local_scope_index(&expr.name));
     }
 
-    fn visit_unary_expr(&mut self, expr: &UnaryExpr, _: &Rc<Expr>) -> () {
+    fn visit_unary_expr(&mut self, expr: &UnaryExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.expression);
     }
 
-    fn visit_binary_expr(&mut self, expr: &BinaryExpr, _: &Rc<Expr>) -> () {
+    fn visit_binary_expr(&mut self, expr: &BinaryExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.left);
         self.resolve_expr(&expr.right);
     }
 
-    fn visit_condition_expr(&mut self, expr: &ConditionExpr, _: &Rc<Expr>) -> () {
+    fn visit_condition_expr(&mut self, expr: &ConditionExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.left);
         self.resolve_expr(&expr.right);
     }
 
-    fn visit_grouping_expr(&mut self, expr: &GroupingExpr, _: &Rc<Expr>) -> () {
+    fn visit_grouping_expr(&mut self, expr: &GroupingExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.expression);
     }
 
-    fn visit_call_expr(&mut self, expr: &CallExpr, _: &Rc<Expr>) -> () {
+    fn visit_call_expr(&mut self, expr: &CallExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.callee);
 
         for argument in &expr.arguments {
@@ -282,16 +346,40 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
         }
     }
 
-    fn visit_get_expr(&mut self, expr: &GetExpr, _: &Rc<Expr>) -> () {
+    fn visit_list_expr(&mut self, expr: &ListExpr, _: &Rc<Expr>) {
+        for element in &expr.elements {
+            self.resolve_expr(element);
+        }
+    }
+
+    fn visit_map_expr(&mut self, expr: &MapExpr, _: &Rc<Expr>) {
+        for (key, value) in &expr.entries {
+            self.resolve_expr(key);
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_index_expr(&mut self, expr: &IndexExpr, _: &Rc<Expr>) {
+        self.resolve_expr(&expr.object);
+        self.resolve_expr(&expr.index);
+    }
+
+    fn visit_set_index_expr(&mut self, expr: &SetIndexExpr, _: &Rc<Expr>) {
+        self.resolve_expr(&expr.object);
+        self.resolve_expr(&expr.index);
+        self.resolve_expr(&expr.value);
+    }
+
+    fn visit_get_expr(&mut self, expr: &GetExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.object);
     }
 
-    fn visit_set_expr(&mut self, expr: &SetExpr, _: &Rc<Expr>) -> () {
+    fn visit_set_expr(&mut self, expr: &SetExpr, _: &Rc<Expr>) {
         self.resolve_expr(&expr.object);
         self.resolve_expr(&expr.value);
     }
 
-    fn visit_this_expr(&mut self, expr: &ThisExpr, _: &Rc<Expr>) -> () {
+    fn visit_this_expr(&mut self, expr: &ThisExpr, _: &Rc<Expr>) {
         match self.class_type {
             ClassType::Class | ClassType::SubClass => {
                 expr.scope_index
@@ -304,7 +392,7 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
         }
     }
 
-    fn visit_super_expr(&mut self, expr: &SuperExpr, _: &Rc<Expr>) -> () {
+    fn visit_super_expr(&mut self, expr: &SuperExpr, _: &Rc<Expr>) {
         match self.class_type {
             ClassType::None => self
                 .error_collector
@@ -1,13 +1,24 @@
-use crate::{lox::ErrorCollector, token::LiteralValue};
+use std::rc::Rc;
+
+use crate::{
+    lox::ErrorCollector,
+    token::{LiteralValue, Span},
+};
 
 use super::token::{Token, TokenType};
 
 pub struct Scanner<'a> {
     error_collector: &'a mut ErrorCollector,
     source: &'a str,
-    bytes: &'a [u8],
+    /// The source, shared by the spans of all tokens.
+    shared_source: Rc<str>,
     line: usize,
+    /// Byte offset of the start of the current line.
+    line_start: usize,
     start: usize,
+    /// Line and column of the start of the current token.
+    start_line: usize,
+    start_column: usize,
     current: usize,
     tokens: Vec<Token>,
 }
@@ -17,18 +28,27 @@ impl<'a> Scanner<'a> {
         Scanner {
             error_collector,
             source,
-            bytes: source.as_bytes(),
+            shared_source: source.into(),
             line: 1,
+            line_start: 0,
             start: 0,
+            start_line: 1,
+            start_column: 1,
             current: 0,
             tokens: Vec::new(),
         }
     }
 
     pub fn scan_tokens(mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            self.scan_token();
+        loop {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.source[self.line_start..self.start].chars().count() + 1;
+
+            if self.is_at_end() {
+                break;
+            }
+            self.scan_token();
         }
 
         self.add_token(TokenType::Eof);
@@ -44,12 +64,16 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             '.' => self.add_token(TokenType::Dot),
             ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
             ';' => self.add_token(TokenType::Semicolon),
             '+' => self.add_token(TokenType::Plus),
             '-' => self.add_token(TokenType::Minus),
             '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
             '/' => {
                 if self.match_char('/') {
                     while !self.is_at_end() && self.peek() != '\n' {
@@ -89,50 +113,39 @@ impl<'a> Scanner<'a> {
             }
             '"' => self.string(),
             ' ' | '\t' => {}
-            '\n' => {
-                self.line += 1;
-            }
+            '\n' => self.new_line(),
             _ => {
                 if is_digit(character) {
                     self.number();
                 } else if is_alpha(character) {
                     self.identifier();
                 } else {
-                    let message = format!("Unexpected character '{}'.", character);
-                    self.error_collector.scanner_error(self.line, &message);
+                    self.error_collector.scanner_error(
+                        self.line,
+                        self.span(),
+                        "Unexpected character.",
+                    );
                 }
             }
         }
     }
 
     fn string(&mut self) {
-        loop {
-            if self.is_at_end() {
-                break;
-            }
-
-            match self.peek() {
-                '\n' => {
-                    self.line += 1;
-                }
+        while !self.is_at_end() {
+            match self.advance() {
+                '\n' => self.new_line(),
                 '"' => {
-                    break;
+                    let lexeme = self.lexeme();
+                    let value = lexeme[1..(lexeme.len() - 1)].to_string();
+                    self.add_full_token(TokenType::String, Some(LiteralValue::String(value)));
+                    return;
                 }
                 _ => {}
             }
-
-            self.advance();
-        }
-
-        if !self.match_char('"') {
-            self.error_collector
-                .scanner_error(self.line, "Unterminated string.");
-            return;
         }
 
-        let lexeme = self.lexeme();
-        let value = lexeme[1..(lexeme.len() - 1)].to_string();
-        self.add_full_token(TokenType::String, Some(LiteralValue::String(value)));
+        self.error_collector
+            .scanner_error(self.line, self.span(), "Unterminated string.");
     }
 
     fn number(&mut self) {
@@ -140,7 +153,8 @@ impl<'a> Scanner<'a> {
             self.advance();
         }
 
-        if self.match_char('.') {
+        if !self.is_at_end() && self.peek() == '.' && self.peek_next().is_some_and(is_digit) {
+            self.advance();
             while !self.is_at_end() && is_digit(self.peek()) {
                 self.advance();
             }
@@ -164,12 +178,18 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        self.bytes[self.current] as char
+        self.source[self.current..].chars().next().unwrap()
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.source[self.current..].chars().nth(1)
     }
 
+    /// Consumes the current character, which can take up more than one byte,
+    /// so that tokens always start and end on character boundaries.
     fn advance(&mut self) -> char {
         let current = self.peek();
-        self.current += 1;
+        self.current += current.len_utf8();
         current
     }
 
@@ -188,30 +208,54 @@ impl<'a> Scanner<'a> {
 
     fn add_full_token(&mut self, token_type: TokenType, literal: Option<LiteralValue>) {
         self.tokens.push(Token {
-            token_type: token_type,
+            token_type,
             line: self.line,
+            span: self.span(),
             literal,
             lexeme: self.lexeme().to_string(),
         })
     }
 
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            source: self.shared_source.clone(),
+            start: self.start,
+            end: self.current,
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
     fn lexeme(&self) -> &'a str {
         &self.source[self.start..self.current]
     }
 }
 
 fn is_digit(character: char) -> bool {
-    character >= '0' && character <= '9'
+    character.is_ascii_digit()
 }
 
 fn is_alpha(character: char) -> bool {
-    (character >= 'A' && character <= 'Z') || (character >= 'a' && character <= 'z')
+    character.is_ascii_alphabetic() || character == '_'
 }
 
 fn is_alpha_numeric(character: char) -> bool {
     is_digit(character) || is_alpha(character)
 }
 
+/// Returns whether `text` would be scanned as a single identifier.
+pub fn is_identifier(text: &str) -> bool {
+    let mut characters = text.chars();
+    characters.next().is_some_and(is_alpha)
+        && characters.all(is_alpha_numeric)
+        && resolve_keyword_type(text).is_none()
+}
+
 fn resolve_keyword_type(lexeme: &str) -> Option<TokenType> {
     match lexeme {
         "var" => Some(TokenType::Var),
@@ -224,6 +268,14 @@ fn resolve_keyword_type(lexeme: &str) -> Option<TokenType> {
         "for" => Some(TokenType::For),
         "while" => Some(TokenType::While),
         "return" => Some(TokenType::Return),
+        "break" => Some(TokenType::Break),
+        "continue" => Some(TokenType::Continue),
+        "throw" => Some(TokenType::Throw),
+        "try" => Some(TokenType::Try),
+        "catch" => Some(TokenType::Catch),
+        "finally" => Some(TokenType::Finally),
+        "import" => Some(TokenType::Import),
+        "export" => Some(TokenType::Export),
         "print" => Some(TokenType::Print),
         "and" => Some(TokenType::And),
         "or" => Some(TokenType::Or),
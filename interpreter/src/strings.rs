@@ -0,0 +1,152 @@
+//! Native functions for working with strings.
+//!
+//! All functions except `str` are also methods of strings, which receive the
+//! string as their first argument: `"abc".len()` is the same as `len("abc")`.
+//!
+//! Positions in strings count characters, not bytes.
+
+use std::rc::Rc;
+
+use crate::interpreter::{BuiltinFunction, List, RuntimeValue};
+
+const NAMES: [&str; 12] = [
+    "len",
+    "substring",
+    "indexOf",
+    "split",
+    "trim",
+    "upper",
+    "lower",
+    "replace",
+    "startsWith",
+    "endsWith",
+    "chars",
+    "str",
+];
+
+/// Returns the string functions, which are defined as globals.
+pub fn functions() -> impl Iterator<Item = BuiltinFunction> {
+    NAMES.iter().map(|name| function(name).unwrap())
+}
+
+/// Returns the native method `name` bound to `string`, if there is one.
+pub fn method(string: &Rc<String>, name: &str) -> Option<RuntimeValue> {
+    // `str` converts any value, so it is not a method.
+    if name == "str" {
+        return None;
+    }
+
+    let method = function(name)?.bind(RuntimeValue::String(string.clone()));
+    Some(RuntimeValue::BuiltinFunction(Rc::new(method)))
+}
+
+fn function(name: &str) -> Option<BuiltinFunction> {
+    let function = match name {
+        "len" => BuiltinFunction::new(name, 1, |arguments| {
+            let length = match &arguments[0] {
+                RuntimeValue::String(string) => string.chars().count(),
+                RuntimeValue::List(list) => list.borrow().len(),
+                RuntimeValue::Map(map) => map.borrow().len(),
+                _ => return Err("Argument must be a string, list or map.".to_string()),
+            };
+            Ok(number(length))
+        }),
+        "substring" => BuiltinFunction::new(name, 3, |arguments| {
+            let string = string_argument(&arguments[0])?;
+            let length = string.chars().count();
+            let start = integer_argument(&arguments[1])?;
+            let end = integer_argument(&arguments[2])?;
+            if start < 0 || start > end || end > length as i64 {
+                return Err(format!(
+                    "Range {}..{} is out of range for a string of length {}.",
+                    start, end, length
+                ));
+            }
+
+            let substring = string
+                .chars()
+                .skip(start as usize)
+                .take((end - start) as usize)
+                .collect();
+            Ok(RuntimeValue::String(Rc::new(substring)))
+        }),
+        "indexOf" => BuiltinFunction::new(name, 2, |arguments| {
+            let string = string_argument(&arguments[0])?;
+            let pattern = string_argument(&arguments[1])?;
+            Ok(match string.find(pattern.as_str()) {
+                Some(offset) => number(string[..offset].chars().count()),
+                None => RuntimeValue::Number(-1.0),
+            })
+        }),
+        "split" => BuiltinFunction::with_heap(name, 2, |heap, arguments| {
+            let string = string_argument(&arguments[0])?;
+            let separator = string_argument(&arguments[1])?;
+            if separator.is_empty() {
+                return Err("Separator must not be empty.".to_string());
+            }
+
+            let elements = string.split(separator.as_str()).map(new_string).collect();
+            Ok(RuntimeValue::List(heap.list(List::new(elements))))
+        }),
+        "trim" => BuiltinFunction::new(name, 1, |arguments| {
+            Ok(new_string(string_argument(&arguments[0])?.trim()))
+        }),
+        "upper" => BuiltinFunction::new(name, 1, |arguments| {
+            Ok(new_string(&string_argument(&arguments[0])?.to_uppercase()))
+        }),
+        "lower" => BuiltinFunction::new(name, 1, |arguments| {
+            Ok(new_string(&string_argument(&arguments[0])?.to_lowercase()))
+        }),
+        "replace" => BuiltinFunction::new(name, 3, |arguments| {
+            let string = string_argument(&arguments[0])?;
+            let from = string_argument(&arguments[1])?;
+            let to = string_argument(&arguments[2])?;
+            Ok(new_string(&string.replace(from.as_str(), to)))
+        }),
+        "startsWith" => BuiltinFunction::new(name, 2, |arguments| {
+            let string = string_argument(&arguments[0])?;
+            let prefix = string_argument(&arguments[1])?;
+            Ok(RuntimeValue::Bool(string.starts_with(prefix.as_str())))
+        }),
+        "endsWith" => BuiltinFunction::new(name, 2, |arguments| {
+            let string = string_argument(&arguments[0])?;
+            let suffix = string_argument(&arguments[1])?;
+            Ok(RuntimeValue::Bool(string.ends_with(suffix.as_str())))
+        }),
+        "chars" => BuiltinFunction::with_heap(name, 1, |heap, arguments| {
+            let elements = string_argument(&arguments[0])?
+                .chars()
+                .map(|character| new_string(character.encode_utf8(&mut [0; 4])))
+                .collect();
+            Ok(RuntimeValue::List(heap.list(List::new(elements))))
+        }),
+        "str" => BuiltinFunction::new(name, 1, |arguments| {
+            Ok(new_string(&arguments[0].to_string()))
+        }),
+        _ => return None,
+    };
+
+    Some(function)
+}
+
+fn string_argument(value: &RuntimeValue) -> Result<&Rc<String>, String> {
+    match value {
+        RuntimeValue::String(string) => Ok(string),
+        _ => Err("Argument must be a string.".to_string()),
+    }
+}
+
+fn integer_argument(value: &RuntimeValue) -> Result<i64, String> {
+    match *value {
+        RuntimeValue::Number(value) if value.fract() == 0.0 => Ok(value as i64),
+        _ => Err("Argument must be an integer.".to_string()),
+    }
+}
+
+fn new_string(string: &str) -> RuntimeValue {
+    RuntimeValue::String(Rc::new(string.to_string()))
+}
+
+fn number(value: usize) -> RuntimeValue {
+    RuntimeValue::Number(value as f64)
+}
@@ -1,3 +1,5 @@
+use std::{fmt, rc::Rc};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single character tokens.
@@ -5,13 +7,17 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Dot,
     Comma,
+    Colon,
     Semicolon,
     Plus,
     Minus,
     Slash,
     Star,
+    Percent,
 
     // One or two-character tokens.
     Bang,
@@ -34,6 +40,14 @@ pub enum TokenType {
     For,
     While,
     Return,
+    Break,
+    Continue,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Import,
+    Export,
     Print,
     And,
     Or,
@@ -63,5 +77,81 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
+    pub span: Span,
     pub literal: Option<LiteralValue>,
 }
+
+/// A range of the source code of a program.
+#[derive(Clone)]
+pub struct Span {
+    /// The whole source code the range is part of.
+    pub source: Rc<str>,
+    /// Byte offset of the start of the range.
+    pub start: usize,
+    /// Byte offset of the end of the range, which is exclusive.
+    pub end: usize,
+    /// Line of the start of the range, starting at 1.
+    pub line: usize,
+    /// Column of the start of the range in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span from the start of this span to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: self.end.max(other.end),
+            ..self.clone()
+        }
+    }
+
+    /// Renders the line, on which this span starts, with the span underlined.
+    ///
+    /// Every line of the snippet is indented, so that it stands apart from
+    /// the error message it accompanies.
+    pub fn snippet(&self) -> String {
+        let source = &self.source;
+        let line_start = source[..self.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line_end = source[self.start..]
+            .find('\n')
+            .map_or(source.len(), |index| self.start + index);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Tabs are kept, so that the underline lines up with the text.
+        let indent: String = source[line_start..self.start]
+            .chars()
+            .map(|character| if character == '\t' { '\t' } else { ' ' })
+            .collect();
+        let length = source[self.start..self.end.clamp(self.start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        let line = self.line.to_string();
+        let gutter = " ".repeat(line.len() + 1);
+        format!(
+            "{gutter} |\n {line} | {text}\n{gutter} | {indent}{}",
+            "^".repeat(length)
+        )
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}..{} ({}:{})",
+            self.start, self.end, self.line, self.column
+        )
+    }
+}
+
+/// Spans are equal if they cover the same range, regardless of the source.
+impl PartialEq for Span {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.end, self.line, self.column)
+            == (other.start, other.end, other.line, other.column)
+    }
+}
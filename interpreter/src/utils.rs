@@ -29,7 +29,7 @@ impl<T> Late<T> {
             // immutable references after value has been set.
             match &*self.value.get() {
                 None => None,
-                Some(value) => Some(&value),
+                Some(value) => Some(value),
             }
         }
     }
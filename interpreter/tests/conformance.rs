@@ -0,0 +1,7 @@
+#[path = "../../test/harness.rs"]
+mod harness;
+
+#[test]
+fn conformance() {
+    harness::run_suite(env!("CARGO_BIN_EXE_rust_lox"), &[]);
+}
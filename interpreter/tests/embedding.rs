@@ -0,0 +1,222 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use rust_lox::{Location, Lox, LoxError, RuntimeValue, StackFrame, WriteOutput};
+
+/// Returns an engine, which discards its output.
+fn quiet_lox() -> Lox {
+    Lox::with_output(WriteOutput {
+        output: io::sink(),
+        errors: io::sink(),
+    })
+}
+
+#[test]
+fn globals_persist_between_runs() {
+    let mut lox = quiet_lox();
+    let result = Rc::new(RefCell::new(RuntimeValue::Nil));
+
+    let captured = result.clone();
+    lox.define_native("capture", 1, move |arguments| {
+        *captured.borrow_mut() = arguments[0].clone();
+        Ok(RuntimeValue::Nil)
+    });
+
+    lox.run("var a = 1;").unwrap();
+    lox.run("capture(a + 1);").unwrap();
+
+    assert!(*result.borrow() == RuntimeValue::Number(2.0));
+}
+
+#[test]
+fn compile_errors_are_returned() {
+    let mut lox = quiet_lox();
+
+    let diagnostics = match lox.run("var a = ;\nvar = 1;") {
+        Err(LoxError::Compile(diagnostics)) => diagnostics,
+        result => panic!("Expected compile errors, got {:?}.", result),
+    };
+
+    let summary: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.message.as_str(),
+                diagnostic.line,
+                diagnostic.span.column,
+                &diagnostic.location,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "Expect expression.",
+                1,
+                9,
+                &Location::Token(";".to_string())
+            ),
+            (
+                "Expect variable name.",
+                2,
+                5,
+                &Location::Token("=".to_string())
+            ),
+        ]
+    );
+    assert!(lox.run("").is_ok());
+}
+
+#[test]
+fn native_errors_become_runtime_errors() {
+    let mut lox = quiet_lox();
+    lox.define_native("fail", 0, |_| Err("Failed.".to_string()));
+
+    let error = lox.run("\nfail();").unwrap_err();
+
+    match &error {
+        LoxError::Runtime(diagnostic) => {
+            assert_eq!(
+                (diagnostic.message.as_str(), diagnostic.line),
+                ("Failed.", 2)
+            );
+        }
+        _ => panic!("Expected a runtime error, got {:?}.", error),
+    }
+    assert_eq!(
+        error.to_string(),
+        "Failed.\n   |\n 2 | fail();\n   |      ^\n[line 2] in script"
+    );
+}
+
+#[test]
+fn runtime_errors_have_a_stack_trace() {
+    let mut lox = quiet_lox();
+
+    let error = lox
+        .run(
+            "class A {\n  \
+               method() {\n    \
+                 fail();\n  \
+               }\n\
+             }\n\
+             fun fail() {\n  \
+               return nil + 1;\n\
+             }\n\
+             A().method();",
+        )
+        .unwrap_err();
+
+    let trace = match &error {
+        LoxError::Runtime(diagnostic) => &diagnostic.trace,
+        _ => panic!("Expected a runtime error, got {:?}.", error),
+    };
+    let frame = |function: Option<&str>, line| StackFrame {
+        function: function.map(str::to_string),
+        line,
+    };
+    assert_eq!(
+        trace,
+        &vec![
+            frame(Some("fail"), 7),
+            frame(Some("A.method"), 3),
+            frame(None, 9)
+        ]
+    );
+    assert!(error
+        .to_string()
+        .ends_with("[line 7] in fail()\n[line 3] in A.method()\n[line 9] in script"));
+}
+
+#[test]
+fn output_and_errors_are_captured_separately() {
+    let output = Rc::new(RefCell::new(WriteOutput {
+        output: vec![],
+        errors: vec![],
+    }));
+    let mut lox = Lox::with_output(output.clone());
+
+    let _ = lox.run("print 1;\nprint \"a\" + nil;");
+    let _ = lox.run("print;");
+
+    let output = output.borrow();
+    assert_eq!(String::from_utf8_lossy(&output.output), "1\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.errors),
+        "Operands must either both be numbers or both be strings.
+   |
+ 2 | print \"a\" + nil;
+   |           ^
+[line 2] in script
+[line 1] Error at ';': Expect expression.
+   |
+ 1 | print;
+   |      ^
+"
+    );
+}
+
+#[test]
+fn interactive_input_prints_trailing_expressions() {
+    let output = Rc::new(RefCell::new(WriteOutput {
+        output: vec![],
+        errors: io::sink(),
+    }));
+    let mut lox = Lox::with_output(output.clone());
+
+    lox.run_interactive("var a = 1;\na + 1").unwrap();
+    lox.run_interactive("a;").unwrap();
+    assert!(lox.run("a + 1").is_err());
+
+    assert_eq!(String::from_utf8_lossy(&output.borrow().output), "2\n");
+}
+
+#[test]
+fn syntax_trees_are_printed_in_prefix_notation() {
+    let mut lox = quiet_lox();
+
+    assert_eq!(
+        lox.syntax_tree("-a * (b + 1) == f(1).c").unwrap(),
+        "(== (* (- a) (group (+ b 1))) (get (call f 1) c))"
+    );
+    assert!(lox.syntax_tree("1 + 2;").is_err());
+}
+
+#[test]
+fn unreadable_files_are_io_errors() {
+    let mut lox = quiet_lox();
+
+    match lox.run_file("does/not/exist.lox") {
+        Err(LoxError::Io(message)) => {
+            assert!(message.starts_with("Could not read 'does/not/exist.lox'"))
+        }
+        result => panic!("Expected an I/O error, got {:?}.", result),
+    }
+}
+
+#[test]
+fn recursion_is_limited_by_the_native_stack() {
+    let mut lox = quiet_lox();
+
+    lox.run("fun depth(n) {\n  if (n == 0) return 0;\n  return 1 + depth(n - 1);\n}")
+        .unwrap();
+    lox.run("depth(200);").unwrap();
+
+    let error = lox.run("fun loop() { loop(); }\nloop();").unwrap_err();
+    let calls = match &error {
+        LoxError::Runtime(diagnostic) => {
+            assert_eq!(diagnostic.message, "Stack overflow.");
+            diagnostic.trace.len() - 1
+        }
+        _ => panic!("Expected a runtime error, got {:?}.", error),
+    };
+    assert!(calls > 200);
+    assert!(error.to_string().ends_with(&format!(
+        "[line 1] in loop()\n\
+         [line 1] in loop()\n\
+         [line 1] in loop()\n\
+         [previous frame repeated {} more times]\n\
+         [line 2] in script",
+        calls - 3
+    )));
+}
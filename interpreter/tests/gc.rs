@@ -0,0 +1,184 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use rust_lox::{Lox, RuntimeValue, WriteOutput};
+
+/// Whether the objects passed to `watch` are still alive, in the order in
+/// which they were passed.
+type Watched = Rc<RefCell<Vec<Box<dyn Fn() -> bool>>>>;
+
+/// Returns an engine, which discards its output, with a native function
+/// `watch`, which keeps track of whether its argument is still alive without
+/// keeping it alive itself.
+fn watching_lox() -> (Lox, Watched) {
+    let mut lox = Lox::with_output(WriteOutput {
+        output: io::sink(),
+        errors: io::sink(),
+    });
+    let watched: Watched = Rc::new(RefCell::new(vec![]));
+
+    let captured = watched.clone();
+    lox.define_native("watch", 1, move |arguments| {
+        let is_alive: Box<dyn Fn() -> bool> = match &arguments[0] {
+            RuntimeValue::DeclaredFunction(function) => {
+                let function = Rc::downgrade(function);
+                Box::new(move || function.strong_count() > 0)
+            }
+            RuntimeValue::Instance(instance) => {
+                let instance = Rc::downgrade(instance);
+                Box::new(move || instance.strong_count() > 0)
+            }
+            RuntimeValue::List(list) => {
+                let list = Rc::downgrade(list);
+                Box::new(move || list.strong_count() > 0)
+            }
+            RuntimeValue::Map(map) => {
+                let map = Rc::downgrade(map);
+                Box::new(move || map.strong_count() > 0)
+            }
+            _ => return Err("Can only watch objects.".to_string()),
+        };
+        captured.borrow_mut().push(is_alive);
+        Ok(RuntimeValue::Nil)
+    });
+
+    (lox, watched)
+}
+
+fn alive(watched: &Watched) -> Vec<bool> {
+    watched.borrow().iter().map(|is_alive| is_alive()).collect()
+}
+
+#[test]
+fn closures_stored_in_their_environment_are_collected() {
+    let (mut lox, watched) = watching_lox();
+
+    lox.run(
+        "fun outer() {\n  \
+           fun inner() { return inner; }\n  \
+           watch(inner);\n\
+         }\n\
+         outer();",
+    )
+    .unwrap();
+
+    assert_eq!(alive(&watched), vec![true]);
+    assert!(lox.collect_garbage() > 0);
+    assert_eq!(alive(&watched), vec![false]);
+}
+
+#[test]
+fn instances_referencing_themselves_are_collected() {
+    let (mut lox, watched) = watching_lox();
+
+    lox.run(
+        "class A {\n  \
+           method() {}\n\
+         }\n\
+         var a = A();\n\
+         a.self = a;\n\
+         watch(a);\n\
+         var b = A();\n\
+         b.method = b.method;\n\
+         watch(b);\n\
+         a = nil;\n\
+         b = nil;",
+    )
+    .unwrap();
+
+    assert_eq!(alive(&watched), vec![true, true]);
+    lox.collect_garbage();
+    assert_eq!(alive(&watched), vec![false, false]);
+}
+
+#[test]
+fn lists_and_maps_containing_themselves_are_collected() {
+    let (mut lox, watched) = watching_lox();
+
+    lox.run(
+        "var list = [];\n\
+         list.push(list);\n\
+         watch(list);\n\
+         var map = {};\n\
+         map[\"map\"] = map;\n\
+         watch(map);\n\
+         list = nil;\n\
+         map = nil;",
+    )
+    .unwrap();
+
+    lox.collect_garbage();
+    assert_eq!(alive(&watched), vec![false, false]);
+}
+
+#[test]
+fn lists_containing_their_own_methods_are_collected() {
+    let (mut lox, watched) = watching_lox();
+
+    lox.run(
+        "var list = [];\n\
+         list.push(list.push);\n\
+         watch(list);\n\
+         var push = list.push;\n\
+         push(push);\n\
+         list = nil;\n\
+         push = nil;",
+    )
+    .unwrap();
+
+    assert_eq!(alive(&watched), vec![true]);
+    lox.collect_garbage();
+    assert_eq!(alive(&watched), vec![false]);
+}
+
+#[test]
+fn maps_containing_their_own_methods_are_collected() {
+    let (mut lox, watched) = watching_lox();
+
+    lox.run(
+        "var map = {};\n\
+         map[\"remove\"] = map.remove;\n\
+         watch(map);\n\
+         map = nil;",
+    )
+    .unwrap();
+
+    assert_eq!(alive(&watched), vec![true]);
+    lox.collect_garbage();
+    assert_eq!(alive(&watched), vec![false]);
+}
+
+#[test]
+fn reachable_objects_survive_collections() {
+    let (mut lox, watched) = watching_lox();
+    let result = Rc::new(RefCell::new(RuntimeValue::Nil));
+
+    let captured = result.clone();
+    lox.define_native("capture", 1, move |arguments| {
+        *captured.borrow_mut() = arguments[0].clone();
+        Ok(RuntimeValue::Nil)
+    });
+
+    lox.run(
+        "class Counter {\n  \
+           init() { this.count = 0; this.self = this; }\n  \
+           increment() { this.count = this.count + 1; return this.count; }\n\
+         }\n\
+         var counter = Counter();\n\
+         watch(counter);\n\
+         fun makeNext() {\n  \
+           var items = [counter];\n  \
+           fun next() { return items[0].increment(); }\n  \
+           return next;\n\
+         }\n\
+         var next = makeNext();\n\
+         watch(next);\n\
+         next();",
+    )
+    .unwrap();
+
+    assert_eq!(lox.collect_garbage(), 0);
+    assert_eq!(alive(&watched), vec![true, true]);
+
+    lox.run("next();\ncapture(counter.self.count);").unwrap();
+    assert!(*result.borrow() == RuntimeValue::Number(2.0));
+}
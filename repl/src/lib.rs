@@ -0,0 +1,112 @@
+//! The interactive session shared by both Lox implementations.
+
+use std::{env, path::PathBuf};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = ". ";
+
+/// Reads the input of an interactive session, with line editing and a history
+/// which is kept between sessions.
+pub struct Repl {
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+impl Repl {
+    /// Creates a session, whose history is stored in the file `history_file`
+    /// in the home directory of the user.
+    pub fn new(history_file: &str) -> Repl {
+        let mut editor = DefaultEditor::new().expect("Could not create line editor.");
+        let history_path = env::home_dir().map(|home| home.join(history_file));
+        if let Some(path) = &history_path {
+            // There is no history before the first session.
+            let _ = editor.load_history(path);
+        }
+
+        Repl {
+            editor,
+            history_path,
+        }
+    }
+
+    /// Reads the next input, or returns `None` at the end of the session.
+    ///
+    /// The session also ends if the input can't be read, after reporting the
+    /// error to stderr.
+    ///
+    /// Lines are read until all braces and parentheses in the input are
+    /// closed, so that a class or function can be entered over multiple lines.
+    /// Ctrl-C discards the input entered so far and starts over.
+    pub fn read(&mut self) -> Option<String> {
+        let mut input = String::new();
+
+        loop {
+            let prompt = match input.is_empty() {
+                true => PROMPT,
+                false => CONTINUATION_PROMPT,
+            };
+
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    input.push_str(&line);
+                    input.push('\n');
+                }
+                Err(ReadlineError::Interrupted) => {
+                    input.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => return None,
+                Err(error) => {
+                    // Without input the session can't go on, so it ends as if
+                    // the input had ended.
+                    eprintln!("Could not read input: {}", error);
+                    return None;
+                }
+            }
+
+            if !is_incomplete(&input) {
+                break;
+            }
+        }
+
+        let input = input.trim_end();
+        if !input.is_empty() {
+            self.add_history(input);
+        }
+        Some(input.to_string())
+    }
+
+    fn add_history(&mut self, input: &str) {
+        let _ = self.editor.add_history_entry(input);
+        if let Some(path) = &self.history_path {
+            // Losing the history is not worth interrupting the session for.
+            let _ = self.editor.save_history(path);
+        }
+    }
+}
+
+/// Returns whether `source` has braces or parentheses, which are not closed
+/// yet.
+///
+/// Brackets in strings and comments are ignored.
+fn is_incomplete(source: &str) -> bool {
+    let mut depth: isize = 0;
+    let mut characters = source.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            // Skips the string. An unterminated one continues on the next line.
+            '"' if !characters.by_ref().any(|character| character == '"') => return true,
+            '/' if characters.peek() == Some(&'/') => {
+                characters.by_ref().find(|&character| character == '\n');
+            }
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
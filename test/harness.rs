@@ -0,0 +1,254 @@
+//! Runs the Lox files in this directory against an implementation and checks
+//! its output against the expectations annotated in the files.
+//!
+//! This file is shared by the integration tests of both implementations, so
+//! that they are held to the same expectations:
+//!
+//! - `// expect: <output>` expects a line of output on stdout.
+//! - `// expect runtime error: <message>` expects a runtime error on the line
+//!   of the annotation.
+//! - `// Error at '<lexeme>': <message>` expects a compile error on the line of
+//!   the annotation, and `// [line <n>] Error<...>` one on line `n`.
+//!
+//! Compile errors are expected to exit with code 65 and runtime errors with
+//! code 70. Files which expect neither have to exit successfully.
+//!
+//! Errors may be followed by a snippet of the offending source code. Snippets
+//! are indented and are not compared against the expectations.
+//!
+//! Files in directories named `modules` are imported by other tests and are
+//! not run by themselves.
+//!
+//! A test which runs for longer than [TIMEOUT] is killed and fails, so that an
+//! implementation which hangs fails the suite instead of blocking it.
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Expectations {
+        let mut expectations = Expectations {
+            output: vec![],
+            compile_errors: vec![],
+            runtime_error: None,
+        };
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let comment = match line.find("// ") {
+                Some(start) => &line[start + 3..],
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if comment.starts_with("Error") {
+                expectations
+                    .compile_errors
+                    .push(format!("[line {}] {}", line_number, comment));
+            } else if comment.starts_with("[line ") {
+                expectations.compile_errors.push(comment.to_string());
+            }
+        }
+
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+/// Runs `binary` with the test at `path` and returns the ways in which its
+/// behavior differs from the expectations in the test.
+fn run_test(binary: &Path, path: &Path) -> Vec<String> {
+    let source = fs::read_to_string(path).unwrap();
+    let expectations = Expectations::parse(&source);
+
+    let output = match run_with_timeout(Command::new(binary).arg(path)) {
+        Some(output) => output,
+        None => return vec![format!("Timed out after {} seconds.", TIMEOUT.as_secs())],
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout: Vec<&str> = stdout.lines().collect();
+    let stderr: Vec<&str> = stderr
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .collect();
+
+    let mut failures = vec![];
+
+    if !expectations.compile_errors.is_empty() {
+        if stderr != expectations.compile_errors {
+            failures.push(format!(
+                "Expected compile errors:\n{}\nGot:\n{}",
+                expectations.compile_errors.join("\n"),
+                stderr.join("\n")
+            ));
+        }
+    } else if let Some((message, line)) = &expectations.runtime_error {
+        match stderr.first() {
+            Some(actual) if actual == message => {
+                let trace = format!("[line {}]", line);
+                if !stderr
+                    .get(1)
+                    .is_some_and(|actual| actual.starts_with(&trace))
+                {
+                    failures.push(format!(
+                        "Expected runtime error on line {}, but got:\n{}",
+                        line,
+                        stderr.join("\n")
+                    ));
+                }
+            }
+            _ => failures.push(format!(
+                "Expected runtime error '{}', but got:\n{}",
+                message,
+                stderr.join("\n")
+            )),
+        }
+    } else if !stderr.is_empty() {
+        failures.push(format!(
+            "Unexpected output on stderr:\n{}",
+            stderr.join("\n")
+        ));
+    }
+
+    if stdout != expectations.output {
+        failures.push(format!(
+            "Expected output:\n{}\nGot:\n{}",
+            expectations.output.join("\n"),
+            stdout.join("\n")
+        ));
+    }
+
+    let exit_code = output.status.code().unwrap_or(-1);
+    if exit_code != expectations.exit_code() {
+        failures.push(format!(
+            "Expected exit code {}, but got {}.",
+            expectations.exit_code(),
+            exit_code
+        ));
+    }
+
+    failures
+}
+
+/// Runs `command` and returns its output, or `None` if it had to be killed
+/// because it did not exit within [TIMEOUT].
+fn run_with_timeout(command: &mut Command) -> Option<Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The pipes are drained while waiting, so that a child which writes a lot
+    // of output does not block on a full pipe.
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+
+    Some(Output {
+        status,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    })
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        pipe.read_to_end(&mut bytes).unwrap();
+        bytes
+    })
+}
+
+/// Returns the paths of all Lox files in `directory` and its subdirectories,
+/// except for the ones in the directories named in `skip` and in `modules`
+/// directories.
+fn collect_tests(directory: &Path, skip: &[&str], tests: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            if name != "modules" && !skip.contains(&name) {
+                collect_tests(&path, skip, tests);
+            }
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            tests.push(path);
+        }
+    }
+}
+
+/// Runs all tests against `binary` and panics with a report of the tests which
+/// failed, if any.
+///
+/// Tests in the directories named in `skip` are not run, which is meant for
+/// features the implementation does not support yet.
+pub fn run_suite(binary: &str, skip: &[&str]) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test");
+    let mut tests = vec![];
+    collect_tests(&directory, skip, &mut tests);
+
+    let mut report = String::new();
+    let mut failed = 0;
+    for test in &tests {
+        let failures = run_test(Path::new(binary), test);
+        if failures.is_empty() {
+            continue;
+        }
+
+        failed += 1;
+        let name = test.strip_prefix(&directory).unwrap_or(test);
+        report.push_str(&format!("\nFAIL {}\n", name.display()));
+        for failure in failures {
+            report.push_str(&failure);
+            report.push('\n');
+        }
+    }
+
+    if failed > 0 {
+        panic!("{}\n{} of {} tests failed.", report, failed, tests.len());
+    }
+}
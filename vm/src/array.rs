@@ -20,9 +20,19 @@ impl<T> Array<T> {
     }
 
     pub fn elements(&self) -> &[T] {
+        if self.elements.is_null() {
+            return &[];
+        }
         unsafe { std::slice::from_raw_parts(self.elements, self.count) }
     }
 
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(index < self.count);
+        unsafe {
+            *self.elements.add(index) = value;
+        }
+    }
+
     pub fn add(&mut self, value: T) -> usize {
         if self.capacity < self.count + 1 {
             let old_capacity = self.capacity;
@@ -1,10 +1,23 @@
-use crate::{array::Array, value::Value};
+use std::rc::Rc;
+
+use crate::{array::Array, scanner::Span, value::Value};
 
 pub enum Op {
     Constant,
     Nil,
     True,
     False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
     Equal,
     Greater,
     Less,
@@ -12,14 +25,27 @@ pub enum Op {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
     Negate,
     Not,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Invoke,
+    SuperInvoke,
+    Closure,
+    CloseUpvalue,
     Return,
+    Class,
+    Inherit,
+    Method,
 }
 
-impl Into<u8> for Op {
-    fn into(self) -> u8 {
-        self as u8
+impl From<Op> for u8 {
+    fn from(op: Op) -> u8 {
+        op as u8
     }
 }
 
@@ -32,6 +58,17 @@ impl TryFrom<u8> for Op {
             x if x == Op::Nil as u8 => Op::Nil,
             x if x == Op::True as u8 => Op::True,
             x if x == Op::False as u8 => Op::False,
+            x if x == Op::Pop as u8 => Op::Pop,
+            x if x == Op::GetLocal as u8 => Op::GetLocal,
+            x if x == Op::SetLocal as u8 => Op::SetLocal,
+            x if x == Op::GetGlobal as u8 => Op::GetGlobal,
+            x if x == Op::DefineGlobal as u8 => Op::DefineGlobal,
+            x if x == Op::SetGlobal as u8 => Op::SetGlobal,
+            x if x == Op::GetUpvalue as u8 => Op::GetUpvalue,
+            x if x == Op::SetUpvalue as u8 => Op::SetUpvalue,
+            x if x == Op::GetProperty as u8 => Op::GetProperty,
+            x if x == Op::SetProperty as u8 => Op::SetProperty,
+            x if x == Op::GetSuper as u8 => Op::GetSuper,
             x if x == Op::Equal as u8 => Op::Equal,
             x if x == Op::Greater as u8 => Op::Greater,
             x if x == Op::Less as u8 => Op::Less,
@@ -39,9 +76,22 @@ impl TryFrom<u8> for Op {
             x if x == Op::Subtract as u8 => Op::Subtract,
             x if x == Op::Multiply as u8 => Op::Multiply,
             x if x == Op::Divide as u8 => Op::Divide,
+            x if x == Op::Modulo as u8 => Op::Modulo,
             x if x == Op::Negate as u8 => Op::Negate,
             x if x == Op::Not as u8 => Op::Not,
+            x if x == Op::Print as u8 => Op::Print,
+            x if x == Op::Jump as u8 => Op::Jump,
+            x if x == Op::JumpIfFalse as u8 => Op::JumpIfFalse,
+            x if x == Op::Loop as u8 => Op::Loop,
+            x if x == Op::Call as u8 => Op::Call,
+            x if x == Op::Invoke as u8 => Op::Invoke,
+            x if x == Op::SuperInvoke as u8 => Op::SuperInvoke,
+            x if x == Op::Closure as u8 => Op::Closure,
+            x if x == Op::CloseUpvalue as u8 => Op::CloseUpvalue,
             x if x == Op::Return as u8 => Op::Return,
+            x if x == Op::Class as u8 => Op::Class,
+            x if x == Op::Inherit as u8 => Op::Inherit,
+            x if x == Op::Method as u8 => Op::Method,
             _ => return Err(()),
         })
     }
@@ -50,7 +100,10 @@ impl TryFrom<u8> for Op {
 pub struct Chunk {
     code: Array<u8>,
     constants: Array<Value>,
-    lines: Array<usize>,
+    /// The span of source code each byte was compiled from.
+    spans: Array<Span>,
+    /// The source code the chunk was compiled from.
+    source: Rc<str>,
 }
 
 impl Chunk {
@@ -58,7 +111,8 @@ impl Chunk {
         Chunk {
             code: Array::new(),
             constants: Array::new(),
-            lines: Array::new(),
+            spans: Array::new(),
+            source: Rc::from(""),
         }
     }
 
@@ -74,13 +128,26 @@ impl Chunk {
         self.constants.elements()
     }
 
-    pub fn lines(&self) -> &[usize] {
-        self.lines.elements()
+    pub fn spans(&self) -> &[Span] {
+        self.spans.elements()
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
     }
 
-    pub fn write(&mut self, value: u8, line: usize) {
+    pub fn set_source(&mut self, source: Rc<str>) {
+        self.source = source;
+    }
+
+    pub fn write(&mut self, value: u8, span: Span) {
         self.code.add(value);
-        self.lines.add(line);
+        self.spans.add(span);
+    }
+
+    /// Overwrites the byte at `offset`, which has already been written.
+    pub fn patch(&mut self, offset: usize, value: u8) {
+        self.code.set(offset, value);
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
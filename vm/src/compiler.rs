@@ -1,12 +1,16 @@
+use std::{io::Write, rc::Rc};
+
 use crate::{
     chunk::{Chunk, Op},
-    debug::DEBUG_PRINT_CODE,
-    scanner::{Scanner, Token, TokenType},
+    object::ObjFunction,
+    scanner::{Scanner, Span, Token, TokenType},
     value::Value,
+    vm::VM,
 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 enum Precedence {
+    #[default]
     None,
     Assignment,
     Or,
@@ -20,12 +24,6 @@ enum Precedence {
     Primary,
 }
 
-impl Default for Precedence {
-    fn default() -> Self {
-        Precedence::None
-    }
-}
-
 impl TryFrom<usize> for Precedence {
     type Error = ();
 
@@ -48,42 +46,40 @@ impl TryFrom<usize> for Precedence {
     }
 }
 
+#[derive(Default)]
 struct ParseRule {
-    prefix: Option<fn(&mut Compiler) -> ()>,
-    infix: Option<fn(&mut Compiler) -> ()>,
+    prefix: Option<fn(&mut Compiler, bool)>,
+    infix: Option<fn(&mut Compiler, bool)>,
     precedence: Precedence,
 }
 
-impl Default for ParseRule {
-    fn default() -> Self {
-        Self {
-            prefix: None,
-            infix: None,
-            precedence: Default::default(),
-        }
-    }
-}
-
 fn make_parse_rule_table() -> Vec<ParseRule> {
     let mut vec = vec![
         (
             TokenType::LeftParen,
             ParseRule {
-                prefix: Some(|c| c.grouping()),
-                infix: None,
-                precedence: Precedence::None,
+                prefix: Some(|c, _| c.grouping()),
+                infix: Some(|c, _| c.call()),
+                precedence: Precedence::Call,
             },
         ),
         (TokenType::RightParen, ParseRule::default()),
         (TokenType::LeftBrace, ParseRule::default()),
         (TokenType::RightBrace, ParseRule::default()),
         (TokenType::Comma, ParseRule::default()),
-        (TokenType::Dot, ParseRule::default()),
+        (
+            TokenType::Dot,
+            ParseRule {
+                prefix: None,
+                infix: Some(|c, can_assign| c.dot(can_assign)),
+                precedence: Precedence::Call,
+            },
+        ),
         (
             TokenType::Minus,
             ParseRule {
-                prefix: Some(|c| c.unary()),
-                infix: Some(|x| x.binary()),
+                prefix: Some(|c, _| c.unary()),
+                infix: Some(|x, _| x.binary()),
                 precedence: Precedence::Term,
             },
         ),
@@ -91,7 +87,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::Plus,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Term,
             },
         ),
@@ -100,7 +96,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::Slash,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Factor,
             },
         ),
@@ -108,14 +104,22 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::Star,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
+                precedence: Precedence::Factor,
+            },
+        ),
+        (
+            TokenType::Percent,
+            ParseRule {
+                prefix: None,
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Factor,
             },
         ),
         (
             TokenType::Bang,
             ParseRule {
-                prefix: Some(|c| c.unary()),
+                prefix: Some(|c, _| c.unary()),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -124,7 +128,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::BangEqual,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Equality,
             },
         ),
@@ -133,7 +137,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::EqualEqual,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Equality,
             },
         ),
@@ -141,7 +145,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::Greater,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Comparison,
             },
         ),
@@ -149,7 +153,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::GreaterEqual,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Comparison,
             },
         ),
@@ -157,7 +161,7 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::Less,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Comparison,
             },
         ),
@@ -165,27 +169,48 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
             TokenType::LessEqual,
             ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Comparison,
             },
         ),
-        (TokenType::Identifier, ParseRule::default()),
-        (TokenType::String, ParseRule::default()),
+        (
+            TokenType::Identifier,
+            ParseRule {
+                prefix: Some(|c, can_assign| c.variable(can_assign)),
+                infix: None,
+                precedence: Precedence::None,
+            },
+        ),
+        (
+            TokenType::String,
+            ParseRule {
+                prefix: Some(|c, _| c.string()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+        ),
         (
             TokenType::Number,
             ParseRule {
-                prefix: Some(|c| c.number()),
+                prefix: Some(|c, _| c.number()),
                 infix: None,
                 precedence: Precedence::None,
             },
         ),
-        (TokenType::And, ParseRule::default()),
+        (
+            TokenType::And,
+            ParseRule {
+                prefix: None,
+                infix: Some(|c, _| c.and()),
+                precedence: Precedence::And,
+            },
+        ),
         (TokenType::Class, ParseRule::default()),
         (TokenType::Else, ParseRule::default()),
         (
             TokenType::False,
             ParseRule {
-                prefix: Some(|c| c.literal()),
+                prefix: Some(|c, _| c.literal()),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -196,20 +221,43 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
         (
             TokenType::Nil,
             ParseRule {
-                prefix: Some(|c| c.literal()),
+                prefix: Some(|c, _| c.literal()),
                 infix: None,
                 precedence: Precedence::None,
             },
         ),
-        (TokenType::Or, ParseRule::default()),
+        (
+            TokenType::Or,
+            ParseRule {
+                prefix: None,
+                infix: Some(|c, _| c.or()),
+                precedence: Precedence::Or,
+            },
+        ),
         (TokenType::Print, ParseRule::default()),
         (TokenType::Return, ParseRule::default()),
-        (TokenType::Super, ParseRule::default()),
-        (TokenType::This, ParseRule::default()),
+        (TokenType::Break, ParseRule::default()),
+        (TokenType::Continue, ParseRule::default()),
+        (
+            TokenType::Super,
+            ParseRule {
+                prefix: Some(|c, _| c.super_()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+        ),
+        (
+            TokenType::This,
+            ParseRule {
+                prefix: Some(|c, _| c.this()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+        ),
         (
             TokenType::True,
             ParseRule {
-                prefix: Some(|c| c.literal()),
+                prefix: Some(|c, _| c.literal()),
                 infix: None,
                 precedence: Precedence::None,
             },
@@ -220,38 +268,721 @@ fn make_parse_rule_table() -> Vec<ParseRule> {
         (TokenType::Eof, ParseRule::default()),
     ];
 
-    vec.sort_by(|a, b| (a.0 as usize).cmp(&(b.0 as usize)));
+    vec.sort_by_key(|(token_type, _)| *token_type as usize);
 
     vec.into_iter().map(|(_, rule)| rule).collect()
 }
 
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+struct Local<'a> {
+    name: Token<'a>,
+    /// Scope depth of the local, or `None` while its initializer is being
+    /// compiled.
+    depth: Option<usize>,
+    /// Whether the local is captured by a closure and needs to be moved to the
+    /// heap when it goes out of scope.
+    is_captured: bool,
+}
+
+struct Upvalue {
+    index: u8,
+    /// Whether the upvalue captures a local of the enclosing function, as
+    /// opposed to one of its upvalues.
+    is_local: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+/// State for a loop whose body is currently being compiled.
+struct Loop {
+    /// Offset of the code which starts the next iteration, which is where
+    /// `continue` jumps to.
+    start: usize,
+    /// Scope depth outside of the body. Locals of deeper scopes are discarded
+    /// when jumping out of the body.
+    scope_depth: usize,
+    /// Offsets of the jumps emitted for `break`, which are patched to jump to
+    /// the end of the loop.
+    breaks: Vec<usize>,
+}
+
+/// State for the function which is currently being compiled.
+struct FunctionCompiler<'a> {
+    function: *mut ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local<'a>>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    /// The loops being compiled in this function, with the innermost loop last.
+    loops: Vec<Loop>,
+}
+
+/// State for the class whose body is currently being compiled.
+struct ClassCompiler<'a> {
+    name: &'a str,
+    has_super_class: bool,
+}
+
 pub struct Compiler<'a> {
     parser: Parser<'a>,
-    current_chunk: &'a mut Chunk,
+    /// The source code, which is shared by the chunks of all functions.
+    source: Rc<str>,
+    vm: &'a mut VM,
     table: Vec<ParseRule>,
+    /// The functions being compiled, with the innermost function last.
+    compilers: Vec<FunctionCompiler<'a>>,
+    /// The classes being compiled, with the innermost class last.
+    classes: Vec<ClassCompiler<'a>>,
 }
 
 impl<'a> Compiler<'a> {
-    pub fn new(source: &'a str, chunk: &'a mut Chunk) -> Compiler<'a> {
-        Compiler {
+    pub fn new(source: &'a str, vm: &'a mut VM) -> Compiler<'a> {
+        let mut compiler = Compiler {
             parser: Parser::new(Scanner::new(source)),
-            current_chunk: chunk,
+            source: Rc::from(source),
+            vm,
             table: make_parse_rule_table(),
+            compilers: vec![],
+            classes: vec![],
+        };
+        compiler.begin_function(FunctionType::Script);
+        compiler
+    }
+
+    /// Compiles the source code into the function of the top level script.
+    ///
+    /// If the source code contains errors, all of them are returned as
+    /// formatted messages instead.
+    pub fn compile(mut self) -> Result<*mut ObjFunction, Vec<String>> {
+        while !self.parser.match_token(TokenType::Eof) {
+            self.declaration();
+        }
+
+        let function = self.end_compiler();
+
+        match self.parser.had_error {
+            true => Err(self.parser.errors),
+            false => Ok(function),
+        }
+    }
+
+    fn begin_function(&mut self, function_type: FunctionType) {
+        let function = self.vm.new_function();
+        self.vm.compiler_roots.push(function);
+        unsafe {
+            (*function).chunk.set_source(self.source.clone());
+        }
+        if function_type != FunctionType::Script {
+            let name = self.parser.previous.unwrap().lexeme;
+            unsafe {
+                (*function).name = self.vm.copy_string(name);
+            }
+        }
+        if let FunctionType::Method | FunctionType::Initializer = function_type {
+            let class_name = self.classes.last().unwrap().name;
+            unsafe {
+                (*function).class_name = self.vm.copy_string(class_name);
+            }
+        }
+
+        let mut locals = Vec::with_capacity(MAX_LOCALS);
+        // The first slot is reserved for the function being called, or the
+        // receiver in methods.
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+        locals.push(Local {
+            name: Token::synthetic(slot_zero),
+            depth: Some(0),
+            is_captured: false,
+        });
+
+        self.compilers.push(FunctionCompiler {
+            function,
+            function_type,
+            locals,
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        });
+    }
+
+    fn current(&mut self) -> &mut FunctionCompiler<'a> {
+        self.compilers.last_mut().unwrap()
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        unsafe { &mut (*self.current().function).chunk }
+    }
+
+    fn declaration(&mut self) {
+        if self.parser.match_token(TokenType::Class) {
+            self.class_declaration();
+        } else if self.parser.match_token(TokenType::Fun) {
+            self.fun_declaration();
+        } else if self.parser.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+
+        // Recovering after every declaration, including those in blocks,
+        // reports independent errors in the same function or class body.
+        if self.parser.panic_mode {
+            let in_block = self.current().scope_depth > 0;
+            self.parser.synchronize(in_block);
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.parser
+            .consume(TokenType::Identifier, "Expect class name.");
+        let class_name = self.parser.previous.unwrap();
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable();
+
+        self.emit_bytes(Op::Class.into(), name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler {
+            name: class_name.lexeme,
+            has_super_class: false,
+        });
+
+        if self.parser.match_token(TokenType::Less) {
+            self.parser
+                .consume(TokenType::Identifier, "Expect super class name.");
+            let super_class_name = self.parser.previous.unwrap();
+            self.variable(false);
+
+            if class_name.lexeme == super_class_name.lexeme {
+                self.parser.error("Class cannot extend itself.");
+            }
+
+            // The super class is stored in a local named `super`, which
+            // methods capture as an upvalue.
+            self.begin_scope();
+            self.add_local(Token::synthetic("super"));
+            self.define_variable(0);
+
+            self.named_variable(class_name, false);
+            self.emit_op(Op::Inherit);
+            self.classes.last_mut().unwrap().has_super_class = true;
+        }
+
+        // Load the class, so that methods can be bound to it.
+        self.named_variable(class_name, false);
+        self.parser
+            .consume(TokenType::LeftBrace, "Expect '{' after class name.");
+        // A broken method may not consume any tokens, so the rest of the body
+        // is left to synchronize after the declaration.
+        while !self.parser.check(TokenType::RightBrace)
+            && !self.parser.check(TokenType::Eof)
+            && !self.parser.panic_mode
+        {
+            self.method();
+        }
+        self.parser
+            .consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_op(Op::Pop);
+
+        if self.classes.pop().unwrap().has_super_class {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.parser
+            .consume(TokenType::Identifier, "Expect method name.");
+        let name = self.parser.previous.unwrap();
+        let constant = self.identifier_constant(name);
+
+        let function_type = match name.lexeme {
+            "init" => FunctionType::Initializer,
+            _ => FunctionType::Method,
+        };
+        self.function(function_type);
+
+        self.emit_bytes(Op::Method.into(), constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        self.begin_function(function_type);
+        self.begin_scope();
+
+        self.parser
+            .consume(TokenType::LeftParen, "Expect '(' before parameters.");
+        if !self.parser.check(TokenType::RightParen) {
+            loop {
+                let function = unsafe { &mut *self.current().function };
+                function.arity += 1;
+                if function.arity > 255 {
+                    self.parser
+                        .error_at_current("Cannot have more than 255 parameters.");
+                }
+
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+
+                if !self.parser.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.parser
+            .consume(TokenType::RightParen, "Expect ')' after parameters.");
+        // Without a brace, the rest of a broken declaration would be compiled
+        // as the body, which only leads to more errors.
+        if self.parser.match_token(TokenType::LeftBrace) {
+            self.block();
+        } else {
+            self.parser.error_at_current("Expect '{' after parameters.");
+        }
+
+        // No end_scope, because the whole frame is discarded on return.
+        let upvalues = std::mem::take(&mut self.current().upvalues);
+        let function = self.end_compiler();
+        unsafe {
+            (*function).upvalue_count = upvalues.len();
+        }
+        let constant = self.make_constant(function.into());
+        self.emit_bytes(Op::Closure.into(), constant);
+
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index);
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+
+        if self.parser.match_token(TokenType::Equal) {
+            self.expression();
+        } else {
+            self.emit_op(Op::Nil);
+        }
+
+        self.parser.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+
+        self.define_variable(global);
+    }
+
+    fn statement(&mut self) {
+        if self.parser.match_token(TokenType::Print) {
+            self.print_statement();
+        } else if self.parser.match_token(TokenType::Return) {
+            self.return_statement();
+        } else if self.parser.match_token(TokenType::Break) {
+            self.break_statement();
+        } else if self.parser.match_token(TokenType::Continue) {
+            self.continue_statement();
+        } else if self.parser.match_token(TokenType::If) {
+            self.if_statement();
+        } else if self.parser.match_token(TokenType::While) {
+            self.while_statement();
+        } else if self.parser.match_token(TokenType::For) {
+            self.for_statement();
+        } else if self.parser.match_token(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
         }
     }
 
-    pub fn compile(&mut self) -> bool {
+    fn block(&mut self) {
+        while !self.parser.check(TokenType::RightBrace) && !self.parser.check(TokenType::Eof) {
+            self.declaration();
+        }
+
+        self.parser
+            .consume(TokenType::RightBrace, "Expect '}' after statement block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let current = self.current();
+        current.scope_depth -= 1;
+        let scope_depth = current.scope_depth;
+
+        let count = self.discard_locals(scope_depth);
+        let locals = &mut self.current().locals;
+        locals.truncate(locals.len() - count);
+    }
+
+    /// Emits the code to discard the locals which are deeper than
+    /// `scope_depth` from the stack and returns their number.
+    ///
+    /// The locals stay declared, since code which jumps out of their scope
+    /// still has to be followed by the rest of the scope.
+    fn discard_locals(&mut self, scope_depth: usize) -> usize {
+        let captured: Vec<bool> = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.unwrap() > scope_depth)
+            .map(|local| local.is_captured)
+            .collect();
+
+        for is_captured in &captured {
+            if *is_captured {
+                self.emit_op(Op::CloseUpvalue);
+            } else {
+                self.emit_op(Op::Pop);
+            }
+        }
+
+        captured.len()
+    }
+
+    fn print_statement(&mut self) {
         self.expression();
         self.parser
-            .consume(TokenType::Eof, "Expect end of expression.");
-        self.end_compiler();
-        !self.parser.had_error
+            .consume(TokenType::Semicolon, "Expect ';' after print statement.");
+        self.emit_op(Op::Print);
+    }
+
+    fn return_statement(&mut self) {
+        if self.current().function_type == FunctionType::Script {
+            self.parser.error("Can't return from top level code.");
+        }
+
+        if self.parser.match_token(TokenType::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.current().function_type == FunctionType::Initializer {
+                self.parser.error("Can't return value from initializer.");
+            }
+
+            self.expression();
+            self.parser
+                .consume(TokenType::Semicolon, "Expect ';' after return value.");
+            self.emit_op(Op::Return);
+        }
+    }
+
+    fn if_statement(&mut self) {
+        self.parser
+            .consume(TokenType::LeftParen, "Expect '(' before if condition.");
+        self.expression();
+        self.parser
+            .consume(TokenType::RightParen, "Expect ')' before if condition.");
+
+        let then_jump = self.emit_jump(Op::JumpIfFalse);
+        self.emit_op(Op::Pop);
+        self.statement();
+
+        let else_jump = self.emit_jump(Op::Jump);
+
+        self.patch_jump(then_jump);
+        self.emit_op(Op::Pop);
+
+        if self.parser.match_token(TokenType::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().count();
+        self.parser
+            .consume(TokenType::LeftParen, "Expect '(' before while condition.");
+        self.expression();
+        self.parser
+            .consume(TokenType::RightParen, "Expect ')' before while condition.");
+
+        let exit_jump = self.emit_jump(Op::JumpIfFalse);
+        self.emit_op(Op::Pop);
+        self.begin_loop(loop_start);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(Op::Pop);
+        self.end_loop();
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.parser
+            .consume(TokenType::LeftParen, "Expect '(' before for initializer.");
+        if self.parser.match_token(TokenType::Semicolon) {
+            // No initializer.
+        } else if self.parser.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().count();
+        let mut exit_jump = None;
+        if !self.parser.match_token(TokenType::Semicolon) {
+            self.expression();
+            self.parser
+                .consume(TokenType::Semicolon, "Expect ';' after for condition.");
+
+            // Jump out of the loop if the condition is false.
+            exit_jump = Some(self.emit_jump(Op::JumpIfFalse));
+            self.emit_op(Op::Pop);
+        }
+
+        if !self.parser.match_token(TokenType::RightParen) {
+            // The increment runs after the body, so jump over it and loop back
+            // to it from the end of the body.
+            let body_jump = self.emit_jump(Op::Jump);
+            let increment_start = self.current_chunk().count();
+            self.expression();
+            self.emit_op(Op::Pop);
+            self.parser
+                .consume(TokenType::RightParen, "Expect ')' after for increment.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.begin_loop(loop_start);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(Op::Pop);
+        }
+
+        self.end_loop();
+        self.end_scope();
+    }
+
+    /// Starts the body of a loop, whose next iteration starts at `start`.
+    fn begin_loop(&mut self, start: usize) {
+        let current = self.current();
+        let scope_depth = current.scope_depth;
+        current.loops.push(Loop {
+            start,
+            scope_depth,
+            breaks: vec![],
+        });
+    }
+
+    /// Ends the innermost loop and makes its `break` statements jump to the
+    /// next instruction.
+    fn end_loop(&mut self) {
+        let current_loop = self.current().loops.pop().unwrap();
+        for offset in current_loop.breaks {
+            self.patch_jump(offset);
+        }
+    }
+
+    fn break_statement(&mut self) {
+        match self.current().loops.last() {
+            Some(current_loop) => {
+                let scope_depth = current_loop.scope_depth;
+                self.discard_locals(scope_depth);
+                let jump = self.emit_jump(Op::Jump);
+                self.current().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            None => self.parser.error("Can't use 'break' outside of a loop."),
+        }
+
+        self.parser
+            .consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+    }
+
+    fn continue_statement(&mut self) {
+        match self.current().loops.last() {
+            Some(current_loop) => {
+                let (start, scope_depth) = (current_loop.start, current_loop.scope_depth);
+                self.discard_locals(scope_depth);
+                self.emit_loop(start);
+            }
+            None => self.parser.error("Can't use 'continue' outside of a loop."),
+        }
+
+        self.parser
+            .consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.parser.consume(
+            TokenType::Semicolon,
+            "Expect ';' after expression statement.",
+        );
+        self.emit_op(Op::Pop);
     }
 
     fn expression(&mut self) {
         self.parse_precedence(Precedence::Assignment);
     }
 
+    fn parse_variable(&mut self, error_message: &str) -> u8 {
+        self.parser.consume(TokenType::Identifier, error_message);
+
+        self.declare_variable();
+        if self.current().scope_depth > 0 {
+            return 0;
+        }
+
+        self.identifier_constant(self.parser.previous.unwrap())
+    }
+
+    fn declare_variable(&mut self) {
+        let current = self.compilers.last().unwrap();
+        if current.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.parser.previous.unwrap();
+        let is_redeclaration = current
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= current.scope_depth))
+            .any(|local| local.name.lexeme == name.lexeme);
+        if is_redeclaration {
+            self.parser
+                .error("Already a variable with this name in this scope.");
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: Token<'a>) {
+        if self.current().locals.len() == MAX_LOCALS {
+            self.parser.error("Too many local variables in function.");
+            return;
+        }
+
+        self.current().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn resolve_local(&mut self, compiler: usize, name: &Token) -> Option<u8> {
+        let (slot, local) = self.compilers[compiler]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.lexeme == name.lexeme)?;
+
+        if local.depth.is_none() {
+            self.parser
+                .error("Can't read local variable in it's own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    /// Resolves `name` to an upvalue of the function compiled by `compiler`,
+    /// capturing it from the enclosing functions as necessary.
+    fn resolve_upvalue(&mut self, compiler: usize, name: &Token) -> Option<u8> {
+        if compiler == 0 {
+            return None;
+        }
+
+        if let Some(local) = self.resolve_local(compiler - 1, name) {
+            self.compilers[compiler - 1].locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(compiler, local, true));
+        }
+
+        if let Some(upvalue) = self.resolve_upvalue(compiler - 1, name) {
+            return Some(self.add_upvalue(compiler, upvalue, false));
+        }
+
+        None
+    }
+
+    fn add_upvalue(&mut self, compiler: usize, index: u8, is_local: bool) -> u8 {
+        let upvalues = &mut self.compilers[compiler].upvalues;
+
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing as u8;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            self.parser.error("Too many closure variables in function.");
+            return 0;
+        }
+
+        upvalues.push(Upvalue { index, is_local });
+        (upvalues.len() - 1) as u8
+    }
+
+    fn mark_initialized(&mut self) {
+        let current = self.current();
+        if current.scope_depth == 0 {
+            return;
+        }
+        current.locals.last_mut().unwrap().depth = Some(current.scope_depth);
+    }
+
+    fn identifier_constant(&mut self, name: Token) -> u8 {
+        let string = self.vm.copy_string(name.lexeme);
+        self.make_constant(string.into())
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.current().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_bytes(Op::DefineGlobal.into(), global);
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        self.named_variable(self.parser.previous.unwrap(), can_assign);
+    }
+
+    fn named_variable(&mut self, name: Token, can_assign: bool) {
+        let current = self.compilers.len() - 1;
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(current, &name) {
+            (Op::GetLocal, Op::SetLocal, slot)
+        } else if let Some(upvalue) = self.resolve_upvalue(current, &name) {
+            (Op::GetUpvalue, Op::SetUpvalue, upvalue)
+        } else {
+            (Op::GetGlobal, Op::SetGlobal, self.identifier_constant(name))
+        };
+
+        if can_assign && self.parser.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_op_at(set_op, name);
+            self.emit_byte(arg);
+        } else {
+            self.emit_bytes(get_op.into(), arg);
+        }
+    }
+
     fn literal(&mut self) {
         match self.parser.previous.unwrap().token_type {
             TokenType::Nil => self.emit_op(Op::Nil),
@@ -266,47 +997,157 @@ impl<'a> Compiler<'a> {
         self.emit_constant(Value::Number(value));
     }
 
+    fn string(&mut self) {
+        let lexeme = self.parser.previous.unwrap().lexeme;
+        // Strip the surrounding quotes.
+        let string = self.vm.copy_string(&lexeme[1..lexeme.len() - 1]);
+        self.emit_constant(string.into());
+    }
+
     fn unary(&mut self) {
-        let operator = self.parser.previous.unwrap().token_type;
+        let operator = self.parser.previous.unwrap();
 
         self.parse_precedence(Precedence::Unary);
 
-        match operator {
-            TokenType::Minus => self.emit_op(Op::Negate),
+        match operator.token_type {
+            TokenType::Minus => self.emit_op_at(Op::Negate, operator),
             TokenType::Bang => self.emit_op(Op::Not),
             _ => {}
         };
     }
 
     fn binary(&mut self) {
-        let operator = self.parser.previous.unwrap().token_type;
-        let rule = self.get_rule(operator);
+        let operator = self.parser.previous.unwrap();
+        let rule = self.get_rule(operator.token_type);
         self.parse_precedence((rule.precedence as usize + 1).try_into().unwrap());
 
-        match operator {
-            TokenType::Plus => self.emit_op(Op::Add),
-            TokenType::Minus => self.emit_op(Op::Subtract),
-            TokenType::Star => self.emit_op(Op::Multiply),
-            TokenType::Slash => self.emit_op(Op::Divide),
+        match operator.token_type {
+            TokenType::Plus => self.emit_op_at(Op::Add, operator),
+            TokenType::Minus => self.emit_op_at(Op::Subtract, operator),
+            TokenType::Star => self.emit_op_at(Op::Multiply, operator),
+            TokenType::Slash => self.emit_op_at(Op::Divide, operator),
+            TokenType::Percent => self.emit_op_at(Op::Modulo, operator),
             TokenType::BangEqual => {
                 self.emit_op(Op::Equal);
                 self.emit_op(Op::Not);
             }
             TokenType::EqualEqual => self.emit_op(Op::Equal),
-            TokenType::Greater => self.emit_op(Op::Greater),
+            TokenType::Greater => self.emit_op_at(Op::Greater, operator),
             TokenType::GreaterEqual => {
-                self.emit_op(Op::Less);
+                self.emit_op_at(Op::Less, operator);
                 self.emit_op(Op::Not);
             }
-            TokenType::Less => self.emit_op(Op::Less),
+            TokenType::Less => self.emit_op_at(Op::Less, operator),
             TokenType::LessEqual => {
-                self.emit_op(Op::Greater);
+                self.emit_op_at(Op::Greater, operator);
                 self.emit_op(Op::Not);
             }
             _ => {}
         }
     }
 
+    fn call(&mut self) {
+        let arg_count = self.argument_list();
+        self.emit_bytes(Op::Call.into(), arg_count);
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.parser
+            .consume(TokenType::Identifier, "Expect property name after '.'.");
+        let name_token = self.parser.previous.unwrap();
+        let name = self.identifier_constant(name_token);
+
+        if can_assign && self.parser.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_op_at(Op::SetProperty, name_token);
+            self.emit_byte(name);
+        } else if self.parser.match_token(TokenType::LeftParen) {
+            // Calling a method directly avoids allocating a bound method.
+            let arg_count = self.argument_list();
+            self.emit_op_at(Op::Invoke, name_token);
+            self.emit_bytes(name, arg_count);
+        } else {
+            self.emit_bytes(Op::GetProperty.into(), name);
+        }
+    }
+
+    fn this(&mut self) {
+        if self.classes.is_empty() {
+            self.parser.error("Can't use 'this' outside of a class.");
+            return;
+        }
+
+        self.variable(false);
+    }
+
+    fn super_(&mut self) {
+        match self.classes.last() {
+            None => self.parser.error("Cannot use super outside of a class."),
+            Some(class) if !class.has_super_class => self
+                .parser
+                .error("Cannot use super in a class that is not a sub class."),
+            _ => {}
+        }
+
+        self.parser
+            .consume(TokenType::Dot, "Expect '.' after super.");
+        self.parser
+            .consume(TokenType::Identifier, "Expect super method.");
+        let name_token = self.parser.previous.unwrap();
+        let name = self.identifier_constant(name_token);
+
+        self.named_variable(Token::synthetic("this"), false);
+        if self.parser.match_token(TokenType::LeftParen) {
+            let arg_count = self.argument_list();
+            self.named_variable(Token::synthetic("super"), false);
+            self.emit_op_at(Op::SuperInvoke, name_token);
+            self.emit_bytes(name, arg_count);
+        } else {
+            self.named_variable(Token::synthetic("super"), false);
+            self.emit_bytes(Op::GetSuper.into(), name);
+        }
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.parser.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.parser.error("Cannot have more than 255 arguments.");
+                }
+                arg_count += 1;
+
+                if !self.parser.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.parser
+            .consume(TokenType::RightParen, "Expect ')' after arguments.");
+        arg_count as u8
+    }
+
+    fn and(&mut self) {
+        let end_jump = self.emit_jump(Op::JumpIfFalse);
+
+        self.emit_op(Op::Pop);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self) {
+        let else_jump = self.emit_jump(Op::JumpIfFalse);
+        let end_jump = self.emit_jump(Op::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op(Op::Pop);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
     fn grouping(&mut self) {
         self.expression();
         self.parser
@@ -318,12 +1159,13 @@ impl<'a> Compiler<'a> {
         let prefix_rule = self
             .get_rule(self.parser.previous.unwrap().token_type)
             .prefix;
+        let can_assign = precedence as usize <= Precedence::Assignment as usize;
         match prefix_rule {
             None => {
                 self.parser.error("Expect expression.");
                 return;
             }
-            Some(prefix_rule) => prefix_rule(self),
+            Some(prefix_rule) => prefix_rule(self, can_assign),
         }
 
         loop {
@@ -331,42 +1173,103 @@ impl<'a> Compiler<'a> {
 
             let rule_has_precedence = precedence as usize <= rule.precedence as usize;
             if !rule_has_precedence {
-                return;
+                break;
             }
 
             let infix_rule = rule.infix.unwrap();
             self.parser.advance();
-            infix_rule(self);
+            infix_rule(self, can_assign);
+        }
+
+        if can_assign && self.parser.match_token(TokenType::Equal) {
+            self.parser.error("Invalid assignment target.");
         }
     }
 
-    fn end_compiler(&mut self) {
+    fn end_compiler(&mut self) -> *mut ObjFunction {
         self.emit_return();
+        let function = self.compilers.pop().unwrap().function;
+        self.vm.compiler_roots.pop();
 
-        if DEBUG_PRINT_CODE {
-            if !self.parser.had_error {
-                self.current_chunk.disassemble("code");
-                println!();
-            }
+        if self.vm.options.disassemble && !self.parser.had_error {
+            let function = unsafe { &*function };
+            let name = match function.name.is_null() {
+                true => "<script>",
+                false => unsafe { &(*function.name).chars },
+            };
+            let out = &mut self.vm.options.debug_output;
+            function
+                .chunk
+                .disassemble(out, name)
+                .and_then(|_| writeln!(out))
+                .expect("Could not write disassembly.");
         }
+
+        function
     }
 
     fn emit_return(&mut self) {
-        self.emit_op(Op::Return)
+        // Initializers implicitly return the instance.
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit_bytes(Op::GetLocal.into(), 0);
+        } else {
+            self.emit_op(Op::Nil);
+        }
+        self.emit_op(Op::Return);
     }
 
     fn emit_op(&mut self, op: Op) {
         self.emit_byte(op.into());
     }
 
+    /// Emits `op` as if it had been compiled from `token`, so that runtime
+    /// errors point at it.
+    fn emit_op_at(&mut self, op: Op, token: Token) {
+        self.emit_byte_at(op.into(), token.span);
+    }
+
+    fn emit_jump(&mut self, instruction: Op) -> usize {
+        self.emit_op(instruction);
+        self.emit_bytes(0xff, 0xff);
+        self.current_chunk().count() - 2
+    }
+
+    /// Points the jump operand at `offset` to the next instruction to be
+    /// emitted.
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk().count() - offset - 2;
+
+        if jump > u16::MAX as usize {
+            self.parser.error("Too much code to jump over.");
+        }
+
+        let [high, low] = (jump as u16).to_be_bytes();
+        self.current_chunk().patch(offset, high);
+        self.current_chunk().patch(offset + 1, low);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(Op::Loop);
+
+        // +2 to adjust for the operand of the loop instruction.
+        let offset = self.current_chunk().count() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.parser.error("Loop body too large.");
+        }
+
+        let [high, low] = (offset as u16).to_be_bytes();
+        self.emit_bytes(high, low);
+    }
+
     fn emit_constant(&mut self, value: Value) {
         let constant = self.make_constant(value);
         self.emit_bytes(Op::Constant.into(), constant);
     }
 
     fn make_constant(&mut self, value: Value) -> u8 {
-        let constant = self.current_chunk.add_constant(value);
-        if constant > std::u8::MAX as usize {
+        let constant = self.current_chunk().add_constant(value);
+        if constant > u8::MAX as usize {
             self.parser.error("Too many constants in one chunk.");
             return 0;
         }
@@ -375,8 +1278,12 @@ impl<'a> Compiler<'a> {
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        self.current_chunk
-            .write(byte, self.parser.previous.as_ref().unwrap().line)
+        let span = self.parser.previous.unwrap().span;
+        self.emit_byte_at(byte, span);
+    }
+
+    fn emit_byte_at(&mut self, byte: u8, span: Span) {
+        self.current_chunk().write(byte, span)
     }
 
     fn emit_bytes(&mut self, byte0: u8, byte1: u8) {
@@ -394,35 +1301,82 @@ struct Parser<'a> {
     current: Token<'a>,
     previous: Option<Token<'a>>,
     had_error: bool,
+    /// The reported errors, in the order they were found.
+    errors: Vec<String>,
     panic_mode: bool,
 }
 
 impl<'a> Parser<'a> {
-    fn new(scanner: Scanner<'a>) -> Parser {
+    fn new(scanner: Scanner<'a>) -> Parser<'a> {
         let mut scanner = scanner;
         let current = scanner.scan_token();
-        Parser {
+        let mut parser = Parser {
             scanner,
             current,
             previous: None,
             had_error: false,
+            errors: vec![],
             panic_mode: false,
-        }
+        };
+        parser.skip_error_tokens();
+        parser
     }
 
     fn advance(&mut self) {
         self.previous = Some(self.current);
+        self.current = self.scanner.scan_token();
+        self.skip_error_tokens();
+    }
 
-        loop {
+    /// Reports and skips error tokens until `current` is a valid token.
+    fn skip_error_tokens(&mut self) {
+        while TokenType::Error == self.current.token_type {
+            self.error_at_current(self.current.lexeme);
             self.current = self.scanner.scan_token();
-            if TokenType::Error != self.current.token_type {
-                break;
+        }
+    }
+
+    /// Skips tokens until a statement boundary, so that errors in the
+    /// following statements can be reported independently of the last one.
+    ///
+    /// The closing brace of the enclosing block is not skipped, so that the
+    /// block still ends where it should.
+    fn synchronize(&mut self, in_block: bool) {
+        self.panic_mode = false;
+
+        while self.current.token_type != TokenType::Eof {
+            if let Some(TokenType::Semicolon) = self.previous.map(|token| token.token_type) {
+                return;
             }
 
-            self.error_at_current(self.current.lexeme);
+            // The same keywords as in the parser of the interpreter, except for
+            // those of statements the VM doesn't support.
+            use TokenType::*;
+            if let Class | Fun | Var | For | If | While | Print | Return | Break | Continue =
+                self.current.token_type
+            {
+                return;
+            }
+            if in_block && self.check(TokenType::RightBrace) {
+                return;
+            }
+
+            self.advance();
         }
     }
 
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) {
         if self.current.token_type == token_type {
             self.advance();
@@ -447,21 +1401,19 @@ impl<'a> Parser<'a> {
         }
         self.panic_mode = true;
 
-        eprint!("[line {}] Error", token.line);
-
-        match token.token_type {
-            TokenType::Eof => {
-                eprint!(" at end");
-            }
-            TokenType::Error => {
-                // Nothing.
-            }
-            _ => {
-                eprint!(" at '{}'", token.lexeme);
-            }
-        }
+        let location = match token.token_type {
+            TokenType::Eof => " at end".to_string(),
+            TokenType::Error => String::new(),
+            _ => format!(" at '{}'", token.lexeme),
+        };
 
-        eprintln!(": {}", message);
+        self.errors.push(format!(
+            "[line {}] Error{}: {}\n{}",
+            token.line,
+            location,
+            message,
+            token.span.snippet(self.scanner.source())
+        ));
         self.had_error = true;
     }
 }
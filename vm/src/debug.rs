@@ -1,63 +1,171 @@
-use crate::chunk::{Chunk, Op};
+use std::io::{self, Write};
 
-pub static DEBUG_PRINT_CODE: bool = true;
-pub static DEBUG_TRACE_EXECUTION: bool = true;
+use crate::{
+    chunk::{Chunk, Op},
+    object::ObjFunction,
+};
 
 impl Chunk {
-    pub fn disassemble(&self, name: &str) {
-        println!("== {} ==", name);
+    pub fn disassemble(&self, out: &mut dyn Write, name: &str) -> io::Result<()> {
+        writeln!(out, "== {} ==", name)?;
 
         let mut offset = 0;
         while offset < self.count() {
-            offset = self.disassemble_instruction(offset);
+            offset = self.disassemble_instruction(out, offset)?;
         }
+        Ok(())
     }
 
-    pub fn disassemble_instruction(&self, offset: usize) -> usize {
-        print!("{:04} ", offset);
+    pub fn disassemble_instruction(&self, out: &mut dyn Write, offset: usize) -> io::Result<usize> {
+        write!(out, "{:04} ", offset)?;
 
-        if offset > 0 && self.lines()[offset] == self.lines()[offset - 1] {
-            print!("   | ");
+        let line = self.spans()[offset].line;
+        if offset > 0 && line == self.spans()[offset - 1].line {
+            write!(out, "   | ")?;
         } else {
-            print!("{:>4} ", self.lines()[offset]);
+            write!(out, "{:>4} ", line)?;
         }
 
         let instruction = self.code()[offset];
         let op_code: Result<Op, ()> = instruction.try_into();
         match op_code {
             Ok(op_code) => match op_code {
-                Op::Constant => self.constant_instruction("OP_CONSTANT", offset),
-                Op::Nil => self.simple_instruction("OP_NIL", offset),
-                Op::True => self.simple_instruction("OP_TRUE", offset),
-                Op::False => self.simple_instruction("OP_FALSE", offset),
-                Op::Equal => self.simple_instruction("OP_EQUAL", offset),
-                Op::Greater => self.simple_instruction("OP_GREATER", offset),
-                Op::Less => self.simple_instruction("OP_LESS", offset),
-                Op::Add => self.simple_instruction("OP_ADD", offset),
-                Op::Subtract => self.simple_instruction("OP_SUBTRACT", offset),
-                Op::Multiply => self.simple_instruction("OP_MULTIPLY", offset),
-                Op::Divide => self.simple_instruction("OP_DIVIDE", offset),
-                Op::Negate => self.simple_instruction("OP_NEGATE", offset),
-                Op::Not => self.simple_instruction("OP_NOT", offset),
-                Op::Return => self.simple_instruction("OP_RETURN", offset),
+                Op::Constant => self.constant_instruction(out, "OP_CONSTANT", offset),
+                Op::Nil => self.simple_instruction(out, "OP_NIL", offset),
+                Op::True => self.simple_instruction(out, "OP_TRUE", offset),
+                Op::False => self.simple_instruction(out, "OP_FALSE", offset),
+                Op::Pop => self.simple_instruction(out, "OP_POP", offset),
+                Op::GetLocal => self.byte_instruction(out, "OP_GET_LOCAL", offset),
+                Op::SetLocal => self.byte_instruction(out, "OP_SET_LOCAL", offset),
+                Op::GetGlobal => self.constant_instruction(out, "OP_GET_GLOBAL", offset),
+                Op::DefineGlobal => self.constant_instruction(out, "OP_DEFINE_GLOBAL", offset),
+                Op::SetGlobal => self.constant_instruction(out, "OP_SET_GLOBAL", offset),
+                Op::GetUpvalue => self.byte_instruction(out, "OP_GET_UPVALUE", offset),
+                Op::SetUpvalue => self.byte_instruction(out, "OP_SET_UPVALUE", offset),
+                Op::GetProperty => self.constant_instruction(out, "OP_GET_PROPERTY", offset),
+                Op::SetProperty => self.constant_instruction(out, "OP_SET_PROPERTY", offset),
+                Op::GetSuper => self.constant_instruction(out, "OP_GET_SUPER", offset),
+                Op::Equal => self.simple_instruction(out, "OP_EQUAL", offset),
+                Op::Greater => self.simple_instruction(out, "OP_GREATER", offset),
+                Op::Less => self.simple_instruction(out, "OP_LESS", offset),
+                Op::Add => self.simple_instruction(out, "OP_ADD", offset),
+                Op::Subtract => self.simple_instruction(out, "OP_SUBTRACT", offset),
+                Op::Multiply => self.simple_instruction(out, "OP_MULTIPLY", offset),
+                Op::Divide => self.simple_instruction(out, "OP_DIVIDE", offset),
+                Op::Modulo => self.simple_instruction(out, "OP_MODULO", offset),
+                Op::Negate => self.simple_instruction(out, "OP_NEGATE", offset),
+                Op::Not => self.simple_instruction(out, "OP_NOT", offset),
+                Op::Print => self.simple_instruction(out, "OP_PRINT", offset),
+                Op::Jump => self.jump_instruction(out, "OP_JUMP", 1, offset),
+                Op::JumpIfFalse => self.jump_instruction(out, "OP_JUMP_IF_FALSE", 1, offset),
+                Op::Loop => self.jump_instruction(out, "OP_LOOP", -1, offset),
+                Op::Call => self.byte_instruction(out, "OP_CALL", offset),
+                Op::Invoke => self.invoke_instruction(out, "OP_INVOKE", offset),
+                Op::SuperInvoke => self.invoke_instruction(out, "OP_SUPER_INVOKE", offset),
+                Op::Closure => self.closure_instruction(out, offset),
+                Op::CloseUpvalue => self.simple_instruction(out, "OP_CLOSE_UPVALUE", offset),
+                Op::Return => self.simple_instruction(out, "OP_RETURN", offset),
+                Op::Class => self.constant_instruction(out, "OP_CLASS", offset),
+                Op::Inherit => self.simple_instruction(out, "OP_INHERIT", offset),
+                Op::Method => self.constant_instruction(out, "OP_METHOD", offset),
             },
             _ => {
-                println!("Unknown opcode {}", instruction);
-                offset + 1
+                writeln!(out, "Unknown opcode {}", instruction)?;
+                Ok(offset + 1)
             }
         }
     }
 
-    fn simple_instruction(&self, name: &str, offset: usize) -> usize {
-        println!("{}", name);
-        offset + 1
+    fn simple_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        writeln!(out, "{}", name)?;
+        Ok(offset + 1)
     }
 
-    fn constant_instruction(&self, name: &str, offset: usize) -> usize {
+    fn byte_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let slot = self.code()[offset + 1];
+        writeln!(out, "{:<16} {:4}", name, slot)?;
+        Ok(offset + 2)
+    }
+
+    fn jump_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        sign: isize,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let jump = u16::from_be_bytes([self.code()[offset + 1], self.code()[offset + 2]]);
+        let target = (offset + 3) as isize + sign * jump as isize;
+        writeln!(out, "{:<16} {:4} -> {}", name, offset, target)?;
+        Ok(offset + 3)
+    }
+
+    fn closure_instruction(&self, out: &mut dyn Write, offset: usize) -> io::Result<usize> {
+        let constant = self.code()[offset + 1];
+        let function = self.constants()[constant as usize];
+        writeln!(out, "{:<16} {:4} {}", "OP_CLOSURE", constant, function)?;
+
+        let mut offset = offset + 2;
+        let function = unsafe { &*(function.as_obj() as *mut ObjFunction) };
+        for _ in 0..function.upvalue_count {
+            let is_local = self.code()[offset];
+            let index = self.code()[offset + 1];
+            writeln!(
+                out,
+                "{:04}    |                     {} {}",
+                offset,
+                if is_local == 1 { "local" } else { "upvalue" },
+                index
+            )?;
+            offset += 2;
+        }
+
+        Ok(offset)
+    }
+
+    fn constant_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let constant = self.code()[offset + 1];
+        writeln!(
+            out,
+            "{:<16} {:4} '{}'",
+            name,
+            constant,
+            self.constants()[constant as usize]
+        )?;
+        Ok(offset + 2)
+    }
+
+    fn invoke_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
         let constant = self.code()[offset + 1];
-        print!("{:<16} {:4} '", name, constant);
-        self.constants()[constant as usize].print();
-        println!("'");
-        offset + 2
+        let arg_count = self.code()[offset + 2];
+        writeln!(
+            out,
+            "{:<16} ({} args) {:4} '{}'",
+            name,
+            arg_count,
+            constant,
+            self.constants()[constant as usize]
+        )?;
+        Ok(offset + 3)
     }
 }
@@ -1,49 +1,49 @@
-use std::{
-    env,
-    fs::File,
-    io::{self, Read, Write},
-    process::exit,
-};
+use std::{env, fs::File, io::Read, process::exit};
 
-use crate::vm::{InterpretResult, VM};
+use lox_repl::Repl;
+
+use crate::vm::{InterpretResult, VmOptions, VM};
 
 pub struct Lox {
     vm: VM,
 }
 
 impl Lox {
-    pub fn new() -> Lox {
-        Lox { vm: VM::new() }
+    pub fn new(options: VmOptions) -> Lox {
+        Lox {
+            vm: VM::new(options),
+        }
     }
 
-    pub fn main(&mut self) {
-        let args = Vec::from_iter(env::args().skip(1));
+    pub fn main() {
+        let mut options = VmOptions::default();
+        let mut args = vec![];
 
-        match args.len() {
-            0 => self.run_prompt(),
-            1 => self.run_file(&args[0]),
-            _ => {
-                print!("Usage: lox [<file>]");
-                exit(1);
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--disassemble" => options.disassemble = true,
+                "--trace" => options.trace = true,
+                "--stress-gc" => options.stress_gc = true,
+                "--log-gc" => options.log_gc = true,
+                _ if arg.starts_with("--") => usage(),
+                _ => args.push(arg),
             }
         }
+
+        let mut lox = Lox::new(options);
+
+        match args.len() {
+            0 => lox.run_prompt(),
+            1 => lox.run_file(&args[0]),
+            _ => usage(),
+        }
     }
 
     fn run_prompt(&mut self) {
-        let mut lines = io::stdin().lines();
-
-        loop {
-            print!("> ");
-            io::stdout().flush().unwrap();
+        let mut repl = Repl::new(".lox_vm_history");
 
-            match lines.next() {
-                Some(line) => {
-                    self.interpret(&line.unwrap());
-                }
-                None => {
-                    return;
-                }
-            }
+        while let Some(input) = repl.read() {
+            self.interpret(&input);
         }
     }
 
@@ -66,3 +66,8 @@ impl Lox {
         self.vm.interpret(source)
     }
 }
+
+fn usage() -> ! {
+    print!("Usage: lox [--disassemble] [--trace] [--stress-gc] [--log-gc] [<file>]");
+    exit(1);
+}
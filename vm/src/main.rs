@@ -3,11 +3,15 @@ mod chunk;
 mod compiler;
 mod debug;
 mod lox;
+mod math;
 mod memory;
+mod object;
+mod output;
 mod scanner;
+mod table;
 mod value;
 mod vm;
 
 fn main() {
-    lox::Lox::new().main();
+    lox::Lox::main();
 }
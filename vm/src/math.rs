@@ -0,0 +1,107 @@
+//! Native functions for working with numbers.
+//!
+//! `random` returns the same sequence of numbers in the VM and in the
+//! interpreter after both have been seeded with `seedRandom`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{value::Value, vm::VM};
+
+/// The largest number of decimals `toFixed` accepts.
+const MAX_DECIMALS: f64 = 100.0;
+
+macro_rules! unary {
+    ($vm:ident, $name:literal, $function:path) => {
+        $vm.define_native($name, 1, |_, args| {
+            Ok(Value::Number($function(number_argument(args[0])?)))
+        })
+    };
+}
+
+macro_rules! binary {
+    ($vm:ident, $name:literal, $function:path) => {
+        $vm.define_native($name, 2, |_, args| {
+            let left = number_argument(args[0])?;
+            let right = number_argument(args[1])?;
+            Ok(Value::Number($function(left, right)))
+        })
+    };
+}
+
+/// Defines the math functions as globals of `vm`.
+pub fn define_natives(vm: &mut VM) {
+    unary!(vm, "floor", f64::floor);
+    unary!(vm, "ceil", f64::ceil);
+    unary!(vm, "round", f64::round);
+    unary!(vm, "abs", f64::abs);
+    unary!(vm, "sqrt", f64::sqrt);
+    unary!(vm, "sin", f64::sin);
+    unary!(vm, "cos", f64::cos);
+    unary!(vm, "log", f64::ln);
+    binary!(vm, "pow", f64::powf);
+    binary!(vm, "min", f64::min);
+    binary!(vm, "max", f64::max);
+
+    vm.define_native("random", 0, |vm, _| {
+        Ok(Value::Number(next_random(&mut vm.random_state)))
+    });
+    vm.define_native("seedRandom", 1, |vm, args| {
+        vm.random_state = number_argument(args[0])?.to_bits();
+        Ok(Value::Nil)
+    });
+    vm.define_native("parseNumber", 1, |_, args| {
+        if !args[0].is_string() {
+            return Err("Argument must be a string.".to_string());
+        }
+
+        let string = unsafe { &(*args[0].as_string()).chars };
+        Ok(match string.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() => Value::Number(value),
+            _ => Value::Nil,
+        })
+    });
+    vm.define_native("toFixed", 2, |vm, args| {
+        let value = number_argument(args[0])?;
+        let decimals = number_argument(args[1])?;
+        if decimals.fract() != 0.0 {
+            return Err("Argument must be an integer.".to_string());
+        }
+        if !(0.0..=MAX_DECIMALS).contains(&decimals) {
+            return Err(format!(
+                "Number of decimals must be between 0 and {}.",
+                MAX_DECIMALS
+            ));
+        }
+
+        let string = format!("{:.*}", decimals as usize, value);
+        Ok(vm.take_string(string).into())
+    });
+}
+
+/// Returns a seed for [VM::random_state], which differs between runs.
+pub fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Advances the SplitMix64 generator `state` and returns a number in the
+/// range `0..1`.
+fn next_random(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    // The top 53 bits fill the mantissa of a double exactly.
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn number_argument(value: Value) -> Result<f64, String> {
+    match value {
+        Value::Number(value) => Ok(value),
+        _ => Err("Argument must be a number.".to_string()),
+    }
+}
@@ -1,8 +1,23 @@
 use std::{
     alloc::{self, Layout},
+    fmt,
+    io::Write,
+    mem::size_of,
     process::exit,
 };
 
+use crate::{
+    object::{
+        Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjNative, ObjString,
+        ObjType, ObjUpvalue,
+    },
+    table::Table,
+    value::Value,
+    vm::VM,
+};
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
 pub fn grow_capacity(capacity: usize) -> usize {
     if capacity < 8 {
         8
@@ -23,14 +38,240 @@ unsafe fn reallocate<T>(ptr: *mut T, old_size: usize, new_size: usize) -> *mut T
     let old_layout = Layout::array::<T>(old_size).unwrap();
 
     if new_size == 0 {
-        alloc::dealloc(ptr as *mut u8, old_layout);
+        if !ptr.is_null() {
+            alloc::dealloc(ptr as *mut u8, old_layout);
+        }
         return std::ptr::null_mut();
     }
 
     let new_layout = Layout::array::<T>(new_size).unwrap();
-    let result = alloc::realloc(ptr as *mut u8, old_layout, new_layout.size());
+    let result = if ptr.is_null() {
+        alloc::alloc(new_layout)
+    } else {
+        alloc::realloc(ptr as *mut u8, old_layout, new_layout.size())
+    };
     if result.is_null() {
         exit(1)
     }
     result as *mut T
 }
+
+impl VM {
+    pub fn collect_garbage(&mut self) {
+        let before = self.bytes_allocated;
+        self.log_gc(format_args!("-- gc begin"));
+
+        self.mark_roots();
+        self.trace_references();
+        // Interned strings are weak references and must not keep strings alive.
+        self.strings.remove_white();
+        self.sweep();
+
+        self.next_gc = self.bytes_allocated * GC_HEAP_GROW_FACTOR;
+
+        let (after, next_gc) = (self.bytes_allocated, self.next_gc);
+        self.log_gc(format_args!("-- gc end"));
+        self.log_gc(format_args!(
+            "   collected {} bytes (from {} to {}) next at {}",
+            before - after,
+            before,
+            after,
+            next_gc
+        ));
+    }
+
+    /// Writes a line to the debug output, if garbage collector logging is
+    /// enabled.
+    fn log_gc(&mut self, line: fmt::Arguments) {
+        if self.options.log_gc {
+            writeln!(self.options.debug_output, "{}", line).expect("Could not write GC log.");
+        }
+    }
+
+    fn mark_roots(&mut self) {
+        for index in 0..self.stack.len() {
+            let value = self.stack[index];
+            self.mark_value(value);
+        }
+
+        for index in 0..self.frames.len() {
+            let closure = self.frames[index].closure;
+            self.mark_object(closure as *mut Obj);
+        }
+
+        let mut upvalue = self.open_upvalues;
+        while !upvalue.is_null() {
+            self.mark_object(upvalue as *mut Obj);
+            upvalue = unsafe { (*upvalue).next };
+        }
+
+        self.mark_table(&self.globals as *const Table);
+
+        self.mark_object(self.init_string as *mut Obj);
+
+        for index in 0..self.compiler_roots.len() {
+            let function = self.compiler_roots[index];
+            self.mark_object(function as *mut Obj);
+        }
+    }
+
+    /// Marks the keys and values of `table`, which must not be freed during
+    /// marking.
+    fn mark_table(&mut self, table: *const Table) {
+        for (key, value) in unsafe { (*table).entries() } {
+            self.mark_object(key as *mut Obj);
+            self.mark_value(value);
+        }
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(object) = value {
+            self.mark_object(object);
+        }
+    }
+
+    pub fn mark_object(&mut self, object: *mut Obj) {
+        if object.is_null() {
+            return;
+        }
+        let obj = unsafe { &mut *object };
+        if obj.is_marked {
+            return;
+        }
+
+        self.log_gc(format_args!("{:p} mark {}", object, Value::Obj(object)));
+
+        obj.is_marked = true;
+        self.gray_stack.push(object);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(object) = self.gray_stack.pop() {
+            self.blacken_object(object);
+        }
+    }
+
+    fn blacken_object(&mut self, object: *mut Obj) {
+        self.log_gc(format_args!("{:p} blacken {}", object, Value::Obj(object)));
+
+        match unsafe { (*object).obj_type } {
+            ObjType::String | ObjType::Native => {}
+            ObjType::Function => {
+                let function = unsafe { &*(object as *mut ObjFunction) };
+                self.mark_object(function.name as *mut Obj);
+                self.mark_object(function.class_name as *mut Obj);
+                for &constant in function.chunk.constants() {
+                    self.mark_value(constant);
+                }
+            }
+            ObjType::Closure => {
+                let closure = unsafe { &*(object as *mut ObjClosure) };
+                self.mark_object(closure.function as *mut Obj);
+                for &upvalue in closure.upvalues.iter() {
+                    self.mark_object(upvalue as *mut Obj);
+                }
+            }
+            ObjType::Upvalue => {
+                if let Some(value) = unsafe { (*(object as *mut ObjUpvalue)).closed } {
+                    self.mark_value(value);
+                }
+            }
+            ObjType::Class => {
+                let class = object as *mut ObjClass;
+                unsafe {
+                    self.mark_object((*class).name as *mut Obj);
+                    self.mark_table(&(*class).methods);
+                }
+            }
+            ObjType::Instance => {
+                let instance = object as *mut ObjInstance;
+                unsafe {
+                    self.mark_object((*instance).class as *mut Obj);
+                    self.mark_table(&(*instance).fields);
+                }
+            }
+            ObjType::BoundMethod => {
+                let bound_method = unsafe { &*(object as *mut ObjBoundMethod) };
+                self.mark_value(bound_method.receiver);
+                self.mark_object(bound_method.method as *mut Obj);
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut previous: *mut Obj = std::ptr::null_mut();
+        let mut object = self.objects;
+        while !object.is_null() {
+            unsafe {
+                if (*object).is_marked {
+                    (*object).is_marked = false;
+                    previous = object;
+                    object = (*object).next;
+                    continue;
+                }
+
+                let unreached = object;
+                object = (*object).next;
+                if previous.is_null() {
+                    self.objects = object;
+                } else {
+                    (*previous).next = object;
+                }
+
+                self.bytes_allocated -= object_size(unreached);
+                self.free_object(unreached);
+            }
+        }
+    }
+
+    pub fn free_objects(&mut self) {
+        let mut object = self.objects;
+        while !object.is_null() {
+            unsafe {
+                let next = (*object).next;
+                self.free_object(object);
+                object = next;
+            }
+        }
+        self.objects = std::ptr::null_mut();
+    }
+
+    unsafe fn free_object(&mut self, object: *mut Obj) {
+        self.log_gc(format_args!(
+            "{:p} free type {:?}",
+            object,
+            (*object).obj_type
+        ));
+
+        match (*object).obj_type {
+            ObjType::String => drop(Box::from_raw(object as *mut ObjString)),
+            ObjType::Function => drop(Box::from_raw(object as *mut ObjFunction)),
+            ObjType::Native => drop(Box::from_raw(object as *mut ObjNative)),
+            ObjType::Closure => drop(Box::from_raw(object as *mut ObjClosure)),
+            ObjType::Upvalue => drop(Box::from_raw(object as *mut ObjUpvalue)),
+            ObjType::Class => drop(Box::from_raw(object as *mut ObjClass)),
+            ObjType::Instance => drop(Box::from_raw(object as *mut ObjInstance)),
+            ObjType::BoundMethod => drop(Box::from_raw(object as *mut ObjBoundMethod)),
+        }
+    }
+}
+
+/// Returns the number of bytes which are accounted to `object`.
+///
+/// Only memory whose size is fixed once the object has been allocated is
+/// included, so that the same size is subtracted when the object is freed.
+pub unsafe fn object_size(object: *mut Obj) -> usize {
+    match (*object).obj_type {
+        ObjType::String => size_of::<ObjString>() + (*(object as *mut ObjString)).chars.capacity(),
+        ObjType::Function => size_of::<ObjFunction>(),
+        ObjType::Native => size_of::<ObjNative>(),
+        ObjType::Closure => {
+            size_of::<ObjClosure>()
+                + (*(object as *mut ObjClosure)).upvalues.capacity() * size_of::<*mut ObjUpvalue>()
+        }
+        ObjType::Upvalue => size_of::<ObjUpvalue>(),
+        ObjType::Class => size_of::<ObjClass>(),
+        ObjType::Instance => size_of::<ObjInstance>(),
+        ObjType::BoundMethod => size_of::<ObjBoundMethod>(),
+    }
+}
@@ -0,0 +1,383 @@
+use std::{fmt, ptr};
+
+use crate::{chunk::Chunk, memory::object_size, table::Table, value::Value, vm::VM};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObjType {
+    String,
+    Function,
+    Native,
+    Closure,
+    Upvalue,
+    Class,
+    Instance,
+    BoundMethod,
+}
+
+/// Header shared by all heap allocated objects.
+///
+/// Every concrete object type is `#[repr(C)]` and starts with an [Obj], so a
+/// pointer to a concrete object can be cast to a pointer to its header and
+/// back.
+#[repr(C)]
+pub struct Obj {
+    pub obj_type: ObjType,
+    pub is_marked: bool,
+    pub next: *mut Obj,
+}
+
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.obj_type {
+            ObjType::String => write!(f, "{}", self.as_string().chars),
+            ObjType::Function => write!(f, "{}", self.as_function()),
+            ObjType::Native => write!(f, "<native fun {}>", self.as_native().name),
+            ObjType::Closure => write!(f, "{}", unsafe { &*self.as_closure().function }),
+            ObjType::Upvalue => write!(f, "upvalue"),
+            ObjType::Class => write!(f, "<{}>", unsafe { &(*self.as_class().name).chars }),
+            ObjType::Instance => write!(f, "<{} instance>", unsafe {
+                &(*(*self.as_instance().class).name).chars
+            }),
+            ObjType::BoundMethod => write!(f, "{}", unsafe {
+                &*(*self.as_bound_method().method).function
+            }),
+        }
+    }
+}
+
+impl Obj {
+    fn new(obj_type: ObjType) -> Obj {
+        Obj {
+            obj_type,
+            is_marked: false,
+            next: ptr::null_mut(),
+        }
+    }
+
+    pub fn as_string(&self) -> &ObjString {
+        unsafe { &*(self as *const Obj as *const ObjString) }
+    }
+
+    pub fn as_function(&self) -> &ObjFunction {
+        unsafe { &*(self as *const Obj as *const ObjFunction) }
+    }
+
+    pub fn as_native(&self) -> &ObjNative {
+        unsafe { &*(self as *const Obj as *const ObjNative) }
+    }
+
+    pub fn as_closure(&self) -> &ObjClosure {
+        unsafe { &*(self as *const Obj as *const ObjClosure) }
+    }
+
+    pub fn as_class(&self) -> &ObjClass {
+        unsafe { &*(self as *const Obj as *const ObjClass) }
+    }
+
+    pub fn as_instance(&self) -> &ObjInstance {
+        unsafe { &*(self as *const Obj as *const ObjInstance) }
+    }
+
+    pub fn as_bound_method(&self) -> &ObjBoundMethod {
+        unsafe { &*(self as *const Obj as *const ObjBoundMethod) }
+    }
+}
+
+#[repr(C)]
+pub struct ObjString {
+    pub obj: Obj,
+    pub hash: u32,
+    pub chars: String,
+}
+
+#[repr(C)]
+pub struct ObjFunction {
+    pub obj: Obj,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// The name of the function or null for the top level script.
+    pub name: *mut ObjString,
+    /// The name of the class of a method or null for other functions.
+    pub class_name: *mut ObjString,
+}
+
+impl fmt::Display for ObjFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_null() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fun {}>", unsafe { &(*self.name).chars })
+        }
+    }
+}
+
+/// A function implemented in Rust, which returns an error message to report a
+/// runtime error.
+pub type NativeFn = fn(vm: &mut VM, args: &[Value]) -> Result<Value, String>;
+
+#[repr(C)]
+pub struct ObjNative {
+    pub obj: Obj,
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+#[repr(C)]
+pub struct ObjClosure {
+    pub obj: Obj,
+    pub function: *mut ObjFunction,
+    pub upvalues: Vec<*mut ObjUpvalue>,
+}
+
+#[repr(C)]
+pub struct ObjUpvalue {
+    pub obj: Obj,
+    /// Index of the captured variable in the VM stack, while the upvalue is
+    /// open.
+    ///
+    /// An index is used instead of a pointer, because the stack may be moved
+    /// when it grows.
+    pub location: usize,
+    /// The captured value, once the variable has left the stack.
+    pub closed: Option<Value>,
+    /// The next open upvalue, which refers to a lower stack slot.
+    pub next: *mut ObjUpvalue,
+}
+
+#[repr(C)]
+pub struct ObjClass {
+    pub obj: Obj,
+    pub name: *mut ObjString,
+    /// The methods of the class, including the ones inherited from its super
+    /// class, which are copied down when the class is created.
+    pub methods: Table,
+}
+
+#[repr(C)]
+pub struct ObjInstance {
+    pub obj: Obj,
+    pub class: *mut ObjClass,
+    pub fields: Table,
+}
+
+/// A method which has been accessed on an instance and remembers it as the
+/// receiver, so that it can be called later.
+#[repr(C)]
+pub struct ObjBoundMethod {
+    pub obj: Obj,
+    pub receiver: Value,
+    pub method: *mut ObjClosure,
+}
+
+impl Value {
+    pub fn is_obj_type(&self, obj_type: ObjType) -> bool {
+        match self {
+            Value::Obj(obj) => unsafe { (**obj).obj_type == obj_type },
+            _ => false,
+        }
+    }
+
+    pub fn is_string(&self) -> bool {
+        self.is_obj_type(ObjType::String)
+    }
+
+    pub fn is_class(&self) -> bool {
+        self.is_obj_type(ObjType::Class)
+    }
+
+    pub fn is_instance(&self) -> bool {
+        self.is_obj_type(ObjType::Instance)
+    }
+
+    pub fn as_string(&self) -> *mut ObjString {
+        match self {
+            Value::Obj(obj) => *obj as *mut ObjString,
+            _ => panic!(),
+        }
+    }
+}
+
+impl From<*mut ObjString> for Value {
+    fn from(string: *mut ObjString) -> Self {
+        Value::Obj(string as *mut Obj)
+    }
+}
+
+impl From<*mut ObjFunction> for Value {
+    fn from(function: *mut ObjFunction) -> Self {
+        Value::Obj(function as *mut Obj)
+    }
+}
+
+impl From<*mut ObjClosure> for Value {
+    fn from(closure: *mut ObjClosure) -> Self {
+        Value::Obj(closure as *mut Obj)
+    }
+}
+
+impl From<*mut ObjNative> for Value {
+    fn from(native: *mut ObjNative) -> Self {
+        Value::Obj(native as *mut Obj)
+    }
+}
+
+impl From<*mut ObjClass> for Value {
+    fn from(class: *mut ObjClass) -> Self {
+        Value::Obj(class as *mut Obj)
+    }
+}
+
+impl From<*mut ObjInstance> for Value {
+    fn from(instance: *mut ObjInstance) -> Self {
+        Value::Obj(instance as *mut Obj)
+    }
+}
+
+impl From<*mut ObjBoundMethod> for Value {
+    fn from(bound_method: *mut ObjBoundMethod) -> Self {
+        Value::Obj(bound_method as *mut Obj)
+    }
+}
+
+impl VM {
+    /// Returns the interned string with the given contents, allocating it
+    /// first if necessary.
+    pub fn copy_string(&mut self, chars: &str) -> *mut ObjString {
+        let hash = hash_string(chars);
+        let interned = self.strings.find_string(chars, hash);
+        if !interned.is_null() {
+            return interned;
+        }
+
+        self.allocate_string(chars.to_string(), hash)
+    }
+
+    /// Like [VM::copy_string] but takes ownership of `chars`, which avoids a
+    /// copy if the string has not been interned yet.
+    pub fn take_string(&mut self, chars: String) -> *mut ObjString {
+        let hash = hash_string(&chars);
+        let interned = self.strings.find_string(&chars, hash);
+        if !interned.is_null() {
+            return interned;
+        }
+
+        self.allocate_string(chars, hash)
+    }
+
+    fn allocate_string(&mut self, chars: String, hash: u32) -> *mut ObjString {
+        let string = self.allocate_object(ObjString {
+            obj: Obj::new(ObjType::String),
+            hash,
+            chars,
+        });
+        // The table grows outside of the allocator which counts
+        // `bytes_allocated`, so this can't trigger a collection and the string
+        // doesn't have to be rooted first.
+        self.strings.set(string, Value::Nil);
+        string
+    }
+
+    pub fn new_function(&mut self) -> *mut ObjFunction {
+        self.allocate_object(ObjFunction {
+            obj: Obj::new(ObjType::Function),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name: ptr::null_mut(),
+            class_name: ptr::null_mut(),
+        })
+    }
+
+    pub fn new_native(
+        &mut self,
+        name: &'static str,
+        arity: usize,
+        function: NativeFn,
+    ) -> *mut ObjNative {
+        self.allocate_object(ObjNative {
+            obj: Obj::new(ObjType::Native),
+            name,
+            arity,
+            function,
+        })
+    }
+
+    pub fn new_closure(&mut self, function: *mut ObjFunction) -> *mut ObjClosure {
+        let upvalue_count = unsafe { (*function).upvalue_count };
+        self.allocate_object(ObjClosure {
+            obj: Obj::new(ObjType::Closure),
+            function,
+            upvalues: Vec::with_capacity(upvalue_count),
+        })
+    }
+
+    pub fn new_upvalue(&mut self, location: usize) -> *mut ObjUpvalue {
+        self.allocate_object(ObjUpvalue {
+            obj: Obj::new(ObjType::Upvalue),
+            location,
+            closed: None,
+            next: ptr::null_mut(),
+        })
+    }
+
+    pub fn new_class(&mut self, name: *mut ObjString) -> *mut ObjClass {
+        self.allocate_object(ObjClass {
+            obj: Obj::new(ObjType::Class),
+            name,
+            methods: Table::new(),
+        })
+    }
+
+    pub fn new_instance(&mut self, class: *mut ObjClass) -> *mut ObjInstance {
+        self.allocate_object(ObjInstance {
+            obj: Obj::new(ObjType::Instance),
+            class,
+            fields: Table::new(),
+        })
+    }
+
+    pub fn new_bound_method(
+        &mut self,
+        receiver: Value,
+        method: *mut ObjClosure,
+    ) -> *mut ObjBoundMethod {
+        self.allocate_object(ObjBoundMethod {
+            obj: Obj::new(ObjType::BoundMethod),
+            receiver,
+            method,
+        })
+    }
+
+    /// Moves `object` to the heap and links it into the list of objects owned
+    /// by the VM. `T` must be one of the `#[repr(C)]` object types.
+    ///
+    /// This may trigger a garbage collection, so all objects which are still
+    /// needed must be reachable from the roots before calling this method.
+    fn allocate_object<T>(&mut self, object: T) -> *mut T {
+        let object = Box::into_raw(Box::new(object));
+        let obj = object as *mut Obj;
+
+        self.bytes_allocated += unsafe { object_size(obj) };
+        if self.options.stress_gc || self.bytes_allocated > self.next_gc {
+            self.collect_garbage();
+        }
+
+        unsafe {
+            (*obj).next = self.objects;
+        }
+        self.objects = obj;
+        object
+    }
+}
+
+/// FNV-1a
+fn hash_string(chars: &str) -> u32 {
+    let mut hash = 2166136261u32;
+    for byte in chars.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
@@ -0,0 +1,53 @@
+use std::{
+    cell::RefCell,
+    io::{self, Stderr, Stdout, Write},
+    rc::Rc,
+};
+
+/// Receives the output of a program and the errors it reports.
+pub trait Output {
+    /// Receives the text printed by a `print` statement.
+    fn print(&mut self, text: &str);
+
+    /// Receives a compile or runtime error, which can span multiple lines.
+    fn error(&mut self, message: &str);
+}
+
+/// [Output] which writes printed text and errors to separate writers, each
+/// followed by a newline.
+pub struct WriteOutput<O: Write, E: Write> {
+    pub output: O,
+    pub errors: E,
+}
+
+impl WriteOutput<Stdout, Stderr> {
+    /// Writes printed text to stdout and errors to stderr.
+    pub fn standard() -> WriteOutput<Stdout, Stderr> {
+        WriteOutput {
+            output: io::stdout(),
+            errors: io::stderr(),
+        }
+    }
+}
+
+impl<O: Write, E: Write> Output for WriteOutput<O, E> {
+    fn print(&mut self, text: &str) {
+        writeln!(self.output, "{}", text).expect("Could not write output.");
+    }
+
+    fn error(&mut self, message: &str) {
+        writeln!(self.errors, "{}", message).expect("Could not write error.");
+    }
+}
+
+/// Allows the host to keep a handle to an output, for example to inspect what
+/// has been captured, while an engine writes to it.
+impl<T: Output + ?Sized> Output for Rc<RefCell<T>> {
+    fn print(&mut self, text: &str) {
+        self.borrow_mut().print(text);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.borrow_mut().error(message);
+    }
+}
@@ -14,6 +14,7 @@ pub enum TokenType {
     Minus,
     Slash,
     Star,
+    Percent,
 
     // One or two-character tokens.
     Bang,
@@ -36,6 +37,8 @@ pub enum TokenType {
     For,
     While,
     Return,
+    Break,
+    Continue,
     Print,
     And,
     Or,
@@ -60,26 +63,107 @@ pub struct Token<'a> {
     pub token_type: TokenType,
     pub lexeme: &'a str,
     pub line: usize,
+    pub span: Span,
+}
+
+impl<'a> Token<'a> {
+    /// Creates a token which does not appear in the source.
+    pub fn synthetic(lexeme: &'a str) -> Token<'a> {
+        Token {
+            token_type: TokenType::Identifier,
+            lexeme,
+            line: 0,
+            span: Span::default(),
+        }
+    }
+}
+
+/// A range of the source code of a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the start of the range.
+    pub start: usize,
+    /// Byte offset of the end of the range, which is exclusive.
+    pub end: usize,
+    /// Line of the start of the range, starting at 1.
+    pub line: usize,
+    /// Column of the start of the range in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    /// Renders the line of `source`, on which this span starts, with the span
+    /// underlined.
+    ///
+    /// Every line of the snippet is indented, so that it stands apart from
+    /// the error message it accompanies.
+    pub fn snippet(&self, source: &str) -> String {
+        let line_start = source[..self.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line_end = source[self.start..]
+            .find('\n')
+            .map_or(source.len(), |index| self.start + index);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Tabs are kept, so that the underline lines up with the text.
+        let indent: String = source[line_start..self.start]
+            .chars()
+            .map(|character| if character == '\t' { '\t' } else { ' ' })
+            .collect();
+        let length = source[self.start..self.end.clamp(self.start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        let line = self.line.to_string();
+        let gutter = " ".repeat(line.len() + 1);
+        format!(
+            "{gutter} |\n {line} | {text}\n{gutter} | {indent}{}",
+            "^".repeat(length)
+        )
+    }
 }
 
 pub struct Scanner<'a> {
+    source: &'a str,
     start: Chars<'a>,
     current: Chars<'a>,
     line: usize,
+    /// Byte offset of the start of the current line.
+    line_start: usize,
+    /// Line of the start of the current token.
+    start_line: usize,
+    /// Column of the start of the current token.
+    start_column: usize,
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: &'a str) -> Scanner {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
+            source,
             start: source.chars(),
             current: source.chars(),
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
+    /// The source code, which is being scanned.
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
     pub fn scan_token(&mut self) -> Token<'a> {
         self.skip_whitespace();
         self.start = self.current.clone();
+        self.start_line = self.line;
+        self.start_column = self.source[self.line_start..self.offset(&self.start)]
+            .chars()
+            .count()
+            + 1;
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
@@ -106,6 +190,7 @@ impl<'a> Scanner<'a> {
             '+' => return self.make_token(TokenType::Plus),
             '/' => return self.make_token(TokenType::Slash),
             '*' => return self.make_token(TokenType::Star),
+            '%' => return self.make_token(TokenType::Percent),
             '!' => {
                 let token_type = match self.match_char('=') {
                     true => TokenType::BangEqual,
@@ -138,11 +223,11 @@ impl<'a> Scanner<'a> {
             _ => {}
         }
 
-        return self.error_token("Unexpected character.");
+        self.error_token("Unexpected character.")
     }
 
     fn is_at_end(&self) -> bool {
-        self.current.as_str().len() == 0
+        self.current.as_str().is_empty()
     }
 
     fn peek(&self) -> char {
@@ -183,16 +268,12 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 '\n' => {
-                    self.line += 1;
                     self.advance();
+                    self.new_line();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                '/' if self.peek_next() == '/' => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
                 _ => return,
@@ -203,8 +284,9 @@ impl<'a> Scanner<'a> {
     fn make_token(&self, token_type: TokenType) -> Token<'a> {
         Token {
             token_type,
-            lexeme: &self.lexeme(),
+            lexeme: self.lexeme(),
             line: self.line,
+            span: self.span(),
         }
     }
 
@@ -213,6 +295,28 @@ impl<'a> Scanner<'a> {
             token_type: TokenType::Error,
             lexeme: message,
             line: self.line,
+            span: self.span(),
+        }
+    }
+
+    /// Records that a line break has just been consumed.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.offset(&self.current);
+    }
+
+    /// Returns the byte offset in the source of the position of `chars`.
+    fn offset(&self, chars: &Chars) -> usize {
+        self.source.len() - chars.as_str().len()
+    }
+
+    /// Returns the span of the current token.
+    fn span(&self) -> Span {
+        Span {
+            start: self.offset(&self.start),
+            end: self.offset(&self.current),
+            line: self.start_line,
+            column: self.start_column,
         }
     }
 
@@ -223,10 +327,9 @@ impl<'a> Scanner<'a> {
 
     fn string(&mut self) -> Token<'a> {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -265,33 +368,30 @@ impl<'a> Scanner<'a> {
     fn identifier_type(&mut self) -> TokenType {
         match self.start.as_str().as_bytes()[0] as char {
             'a' => return self.check_keyword(1, "nd", TokenType::And),
-            'c' => return self.check_keyword(1, "class", TokenType::Class),
+            'b' => return self.check_keyword(1, "reak", TokenType::Break),
+            'c' if self.lexeme().len() > 1 => match self.lexeme().as_bytes()[1] as char {
+                'l' => return self.check_keyword(2, "ass", TokenType::Class),
+                'o' => return self.check_keyword(2, "ntinue", TokenType::Continue),
+                _ => {}
+            },
             'e' => return self.check_keyword(1, "lse", TokenType::Else),
-            'f' => {
-                if self.start.as_str().len() > 1 {
-                    match self.start.as_str().as_bytes()[1] as char {
-                        'a' => return self.check_keyword(2, "lse", TokenType::False),
-                        'o' => return self.check_keyword(2, "r", TokenType::For),
-                        'u' => return self.check_keyword(2, "n", TokenType::Fun),
-                        _ => {}
-                    }
-                }
-            }
+            'f' if self.lexeme().len() > 1 => match self.lexeme().as_bytes()[1] as char {
+                'a' => return self.check_keyword(2, "lse", TokenType::False),
+                'o' => return self.check_keyword(2, "r", TokenType::For),
+                'u' => return self.check_keyword(2, "n", TokenType::Fun),
+                _ => {}
+            },
             'i' => return self.check_keyword(1, "f", TokenType::If),
             'n' => return self.check_keyword(1, "il", TokenType::Nil),
             'o' => return self.check_keyword(1, "r", TokenType::Or),
             'p' => return self.check_keyword(1, "rint", TokenType::Print),
             'r' => return self.check_keyword(1, "eturn", TokenType::Return),
             's' => return self.check_keyword(1, "uper", TokenType::Super),
-            't' => {
-                if self.start.as_str().len() > 1 {
-                    match self.start.as_str().as_bytes()[1] as char {
-                        'h' => return self.check_keyword(2, "is", TokenType::This),
-                        'r' => return self.check_keyword(2, "ue", TokenType::True),
-                        _ => {}
-                    }
-                }
-            }
+            't' if self.lexeme().len() > 1 => match self.lexeme().as_bytes()[1] as char {
+                'h' => return self.check_keyword(2, "is", TokenType::This),
+                'r' => return self.check_keyword(2, "ue", TokenType::True),
+                _ => {}
+            },
             'v' => return self.check_keyword(1, "ar", TokenType::Var),
             'w' => return self.check_keyword(1, "hile", TokenType::While),
             _ => {}
@@ -310,9 +410,9 @@ impl<'a> Scanner<'a> {
 }
 
 fn is_digit(char: char) -> bool {
-    char >= '0' && char <= '9'
+    char.is_ascii_digit()
 }
 
 fn is_alpha(char: char) -> bool {
-    (char >= 'A' && char <= 'Z') || (char >= 'a' && char <= 'z') || char == '_'
+    char.is_ascii_alphabetic() || char == '_'
 }
@@ -0,0 +1,209 @@
+use std::ptr;
+
+use crate::{
+    memory::{free_array, grow_array, grow_capacity},
+    object::ObjString,
+    value::Value,
+};
+
+const TABLE_MAX_LOAD: f64 = 0.75;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    key: *mut ObjString,
+    value: Value,
+}
+
+impl Entry {
+    fn is_tombstone(&self) -> bool {
+        self.key.is_null() && self.value != Value::Nil
+    }
+}
+
+/// Hash table with open addressing and linear probing, keyed by interned
+/// strings.
+///
+/// Because keys are interned, they are compared by identity.
+pub struct Table {
+    count: usize,
+    capacity: usize,
+    entries: *mut Entry,
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table {
+            count: 0,
+            capacity: 0,
+            entries: ptr::null_mut(),
+        }
+    }
+
+    pub fn get(&self, key: *mut ObjString) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let entry = unsafe { &*find_entry(self.entries, self.capacity, key) };
+        if entry.key.is_null() {
+            return None;
+        }
+
+        Some(entry.value)
+    }
+
+    /// Sets the value for `key` and returns whether `key` is new to the table.
+    pub fn set(&mut self, key: *mut ObjString, value: Value) -> bool {
+        if (self.count + 1) as f64 > self.capacity as f64 * TABLE_MAX_LOAD {
+            self.adjust_capacity(grow_capacity(self.capacity));
+        }
+
+        let entry = unsafe { &mut *find_entry(self.entries, self.capacity, key) };
+        let is_new_key = entry.key.is_null();
+        // Tombstones are already counted.
+        if is_new_key && entry.value == Value::Nil {
+            self.count += 1;
+        }
+
+        entry.key = key;
+        entry.value = value;
+        is_new_key
+    }
+
+    pub fn delete(&mut self, key: *mut ObjString) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let entry = unsafe { &mut *find_entry(self.entries, self.capacity, key) };
+        if entry.key.is_null() {
+            return false;
+        }
+
+        // Leave a tombstone behind, so probing continues past this entry.
+        entry.key = ptr::null_mut();
+        entry.value = Value::Bool(true);
+        true
+    }
+
+    /// Copies all entries of `from` into this table.
+    pub fn add_all(&mut self, from: &Table) {
+        for (key, value) in from.entries() {
+            self.set(key, value);
+        }
+    }
+
+    /// Returns the key which has the given contents or null, if the table does
+    /// not contain such a key.
+    pub fn find_string(&self, chars: &str, hash: u32) -> *mut ObjString {
+        if self.count == 0 {
+            return ptr::null_mut();
+        }
+
+        let mut index = hash as usize % self.capacity;
+        loop {
+            let entry = unsafe { &*self.entries.add(index) };
+            if entry.key.is_null() {
+                if !entry.is_tombstone() {
+                    return ptr::null_mut();
+                }
+            } else {
+                let key = unsafe { &*entry.key };
+                if key.hash == hash && key.chars == chars {
+                    return entry.key;
+                }
+            }
+
+            index = (index + 1) % self.capacity;
+        }
+    }
+
+    /// Returns an iterator over the keys and values in the table.
+    pub fn entries(&self) -> impl Iterator<Item = (*mut ObjString, Value)> + '_ {
+        (0..self.capacity).filter_map(move |index| {
+            let entry = unsafe { &*self.entries.add(index) };
+            match entry.key.is_null() {
+                true => None,
+                false => Some((entry.key, entry.value)),
+            }
+        })
+    }
+
+    /// Deletes all entries whose key has not been marked by the garbage
+    /// collector.
+    pub fn remove_white(&mut self) {
+        for index in 0..self.capacity {
+            let key = unsafe { (*self.entries.add(index)).key };
+            if !key.is_null() && unsafe { !(*key).obj.is_marked } {
+                self.delete(key);
+            }
+        }
+    }
+
+    fn adjust_capacity(&mut self, capacity: usize) {
+        let entries = unsafe { grow_array::<Entry>(ptr::null_mut(), 0, capacity) };
+        for index in 0..capacity {
+            unsafe {
+                entries.add(index).write(Entry {
+                    key: ptr::null_mut(),
+                    value: Value::Nil,
+                });
+            }
+        }
+
+        // Tombstones are not copied, so recount.
+        self.count = 0;
+        for index in 0..self.capacity {
+            let entry = unsafe { &*self.entries.add(index) };
+            if entry.key.is_null() {
+                continue;
+            }
+
+            let dest = unsafe { &mut *find_entry(entries, capacity, entry.key) };
+            dest.key = entry.key;
+            dest.value = entry.value;
+            self.count += 1;
+        }
+
+        unsafe {
+            free_array(self.entries, self.capacity);
+        }
+        self.entries = entries;
+        self.capacity = capacity;
+    }
+}
+
+fn find_entry(entries: *mut Entry, capacity: usize, key: *mut ObjString) -> *mut Entry {
+    let mut index = unsafe { (*key).hash } as usize % capacity;
+    let mut tombstone: *mut Entry = ptr::null_mut();
+
+    loop {
+        let entry = unsafe { entries.add(index) };
+        let entry_ref = unsafe { &*entry };
+
+        if entry_ref.key.is_null() {
+            if !entry_ref.is_tombstone() {
+                // Reuse the first tombstone we passed, if any.
+                return if tombstone.is_null() {
+                    entry
+                } else {
+                    tombstone
+                };
+            } else if tombstone.is_null() {
+                tombstone = entry;
+            }
+        } else if entry_ref.key == key {
+            return entry;
+        }
+
+        index = (index + 1) % capacity;
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        unsafe {
+            free_array(self.entries, self.capacity);
+        }
+    }
+}
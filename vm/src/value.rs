@@ -1,23 +1,39 @@
+use std::fmt;
+
+use crate::object::Obj;
+
 #[derive(Clone, Copy)]
 pub enum Value {
     Nil,
     Bool(bool),
     Number(f64),
+    Obj(*mut Obj),
 }
 
 impl Value {
-    pub fn print(&self) {
+    pub fn as_obj(&self) -> *mut Obj {
         match self {
-            Value::Nil => print!("nil"),
-            Value::Bool(value) => print!("{}", value),
-            Value::Number(value) => print!("{}", value),
+            Value::Obj(obj) => *obj,
+            _ => panic!(),
         }
     }
 
     pub fn is_falsy(&self) -> bool {
         match self {
+            Value::Nil => true,
             Value::Bool(value) => !value,
-            _ => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Obj(obj) => write!(f, "{}", unsafe { &**obj }),
         }
     }
 }
@@ -29,6 +45,8 @@ impl PartialEq for Value {
             (Nil, Nil) => true,
             (Bool(l), Bool(r)) => l == r,
             (Number(l), Number(r)) => l == r,
+            // Strings are interned, so all objects can be compared by identity.
+            (Obj(l), Obj(r)) => l == r,
             _ => false,
         }
     }
@@ -1,9 +1,19 @@
-use std::slice;
+use std::{
+    io::{self, Write},
+    ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     chunk::{Chunk, Op},
     compiler::Compiler,
-    debug::DEBUG_TRACE_EXECUTION,
+    math,
+    object::{
+        NativeFn, Obj, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjString, ObjType,
+        ObjUpvalue,
+    },
+    output::{Output, WriteOutput},
+    table::Table,
     value::Value,
 };
 
@@ -13,28 +23,147 @@ pub enum InterpretResult {
     RuntimeError,
 }
 
-const INITIAL_STACK_CAPACITY: usize = 256;
+const FRAMES_MAX: usize = 64;
+const STACK_MAX: usize = FRAMES_MAX * (u8::MAX as usize + 1);
+
+pub struct CallFrame {
+    pub closure: *mut ObjClosure,
+    ip: usize,
+    /// Index of the first stack slot which belongs to this frame.
+    slots: usize,
+}
+
+/// Options which control the debug output of the [VM].
+pub struct VmOptions {
+    /// Whether to disassemble each function after it has been compiled.
+    pub disassemble: bool,
+    /// Whether to print the stack and the instruction before executing each
+    /// instruction.
+    pub trace: bool,
+    /// Whether to collect garbage before every allocation.
+    pub stress_gc: bool,
+    /// Whether to log what the garbage collector does.
+    pub log_gc: bool,
+    /// Where disassembly, traces and the garbage collector log are written
+    /// to, so that they can be kept apart from the output of the program.
+    pub debug_output: Box<dyn Write>,
+    /// Where the program prints to and errors are reported to.
+    pub output: Box<dyn Output>,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        VmOptions {
+            disassemble: false,
+            trace: false,
+            stress_gc: false,
+            log_gc: false,
+            debug_output: Box::new(io::stderr()),
+            output: Box::new(WriteOutput::standard()),
+        }
+    }
+}
 
 pub struct VM {
-    stack: Vec<Value>,
+    pub options: VmOptions,
+    pub stack: Vec<Value>,
+    pub frames: Vec<CallFrame>,
+    pub globals: Table,
+    /// Upvalues which still point into the stack, sorted by stack slot from
+    /// top to bottom.
+    pub open_upvalues: *mut ObjUpvalue,
+    /// Interned strings. Only the keys are used.
+    ///
+    /// The table does not keep the strings alive. Strings which are otherwise
+    /// unreachable are removed from it during garbage collection.
+    pub strings: Table,
+    /// Linked list of all objects allocated by the VM.
+    pub objects: *mut Obj,
+    /// The interned name of initializer methods.
+    pub init_string: *mut ObjString,
+    /// Functions which are currently being compiled and are not reachable
+    /// from any other root yet.
+    pub compiler_roots: Vec<*mut ObjFunction>,
+    /// Objects which have been marked but whose references have not been
+    /// traced yet.
+    pub gray_stack: Vec<*mut Obj>,
+    pub bytes_allocated: usize,
+    /// The value of [VM::bytes_allocated] at which the next collection is
+    /// triggered.
+    pub next_gc: usize,
+    /// The state of the generator behind the `random` native.
+    pub random_state: u64,
 }
 
 impl VM {
-    pub fn new() -> VM {
-        VM {
-            stack: Vec::with_capacity(INITIAL_STACK_CAPACITY),
-        }
+    pub fn new(options: VmOptions) -> VM {
+        let mut vm = VM {
+            options,
+            stack: Vec::with_capacity(STACK_MAX),
+            frames: Vec::with_capacity(FRAMES_MAX),
+            globals: Table::new(),
+            open_upvalues: ptr::null_mut(),
+            strings: Table::new(),
+            objects: ptr::null_mut(),
+            init_string: ptr::null_mut(),
+            compiler_roots: vec![],
+            gray_stack: vec![],
+            bytes_allocated: 0,
+            next_gc: 1024 * 1024,
+            random_state: math::time_seed(),
+        };
+
+        vm.init_string = vm.copy_string("init");
+
+        vm.define_native("clock", 0, |_, _| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as f64
+                / 1000.0;
+            Ok(Value::Number(now))
+        });
+        math::define_natives(&mut vm);
+
+        vm
     }
 
     pub fn interpret(&mut self, source: &str) -> InterpretResult {
-        let mut chunk = Chunk::new();
-        let mut compiler = Compiler::new(source, &mut chunk);
+        let function = match Compiler::new(source, self).compile() {
+            Ok(function) => function,
+            Err(errors) => {
+                for error in &errors {
+                    self.options.output.error(error);
+                }
+                return InterpretResult::CompileError;
+            }
+        };
 
-        if !compiler.compile() {
-            return InterpretResult::CompileError;
-        }
+        self.push(function.into());
+        let closure = self.new_closure(function);
+        self.pop();
+        self.push(closure.into());
+        self.call(closure, 0);
+
+        self.run()
+    }
 
-        Runner::new(&mut self.stack, &chunk).run()
+    pub fn define_native(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        // Both objects are kept on the stack, so that they are reachable if
+        // allocating the other one triggers a collection.
+        let native = self.new_native(name, arity, function);
+        self.push(native.into());
+        let name = self.copy_string(name);
+        self.push(name.into());
+        self.globals.set(name, native.into());
+        self.pop();
+        self.pop();
+    }
+}
+
+impl Drop for VM {
+    fn drop(&mut self) {
+        self.free_objects();
     }
 }
 
@@ -51,47 +180,25 @@ macro_rules! binary_op {
                     $self.push(Value::$result_type(a $op b));
                     None
                 } else {
-                    $self.runtime_error("Operands mut be numbers.")
+                    $self.runtime_error("Operands must both be numbers.")
                 }
             } else {
-                $self.runtime_error("Operands mut be numbers.")
+                $self.runtime_error("Operands must both be numbers.")
             }
         }
     };
 }
 
-struct Runner<'a> {
-    stack: &'a mut Vec<Value>,
-    chunk: &'a Chunk,
-    ip: slice::Iter<'a, u8>,
-}
-
-impl<'a> Runner<'a> {
-    fn new(stack: &'a mut Vec<Value>, chunk: &'a Chunk) -> Self {
-        Self {
-            stack,
-            chunk,
-            ip: chunk.code().iter(),
-        }
-    }
-
+impl VM {
     fn run(&mut self) -> InterpretResult {
-        if DEBUG_TRACE_EXECUTION {
-            println!("!! Begin Execution !!")
+        if self.options.trace {
+            writeln!(self.options.debug_output, "!! Begin Execution !!")
+                .expect("Could not write trace.");
         }
 
         loop {
-            if DEBUG_TRACE_EXECUTION {
-                print!(" ");
-                for value in self.stack.iter() {
-                    print!("[ ");
-                    value.print();
-                    print!(" ]");
-                }
-                println!();
-
-                self.chunk
-                    .disassemble_instruction(self.instruction_offset());
+            if self.options.trace {
+                self.trace_instruction().expect("Could not write trace.");
             }
 
             let instruction = self.read_byte();
@@ -115,6 +222,109 @@ impl<'a> Runner<'a> {
                     self.push(Value::Bool(false));
                     None
                 }
+                Op::Pop => {
+                    self.pop();
+                    None
+                }
+                Op::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.push(self.stack[slot]);
+                    None
+                }
+                Op::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = *self.peek(0);
+                    None
+                }
+                Op::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(name) {
+                        Some(value) => {
+                            self.push(value);
+                            None
+                        }
+                        None => self
+                            .runtime_error(&format!("Variable '{}' is not defined.", unsafe {
+                                &(*name).chars
+                            })),
+                    }
+                }
+                Op::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = *self.peek(0);
+                    self.globals.set(name, value);
+                    self.pop();
+                    None
+                }
+                Op::SetGlobal => {
+                    let name = self.read_string();
+                    let value = *self.peek(0);
+                    if self.globals.set(name, value) {
+                        self.globals.delete(name);
+                        self.runtime_error(&format!(
+                            "Cannot assign to undefined variable '{}'.",
+                            unsafe { &(*name).chars }
+                        ))
+                    } else {
+                        None
+                    }
+                }
+                Op::GetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = unsafe { &*self.upvalue(slot) };
+                    let value = match upvalue.closed {
+                        Some(value) => value,
+                        None => self.stack[upvalue.location],
+                    };
+                    self.push(value);
+                    None
+                }
+                Op::SetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let value = *self.peek(0);
+                    let upvalue = unsafe { &mut *self.upvalue(slot) };
+                    match upvalue.closed {
+                        Some(_) => upvalue.closed = Some(value),
+                        None => self.stack[upvalue.location] = value,
+                    }
+                    None
+                }
+                Op::GetProperty => {
+                    if !self.peek(0).is_instance() {
+                        self.runtime_error("Only instances have properties.")
+                    } else {
+                        let instance = self.peek(0).as_obj() as *mut ObjInstance;
+                        let name = self.read_string();
+
+                        match unsafe { (*instance).fields.get(name) } {
+                            Some(value) => {
+                                self.pop();
+                                self.push(value);
+                                None
+                            }
+                            None => self.bind_method(unsafe { (*instance).class }, name),
+                        }
+                    }
+                }
+                Op::SetProperty => {
+                    if !self.peek(1).is_instance() {
+                        self.runtime_error("Only instances have properties.")
+                    } else {
+                        let instance = self.peek(1).as_obj() as *mut ObjInstance;
+                        let name = self.read_string();
+                        let value = self.pop();
+                        unsafe { (*instance).fields.set(name, value) };
+                        // Replace the instance with the assigned value.
+                        self.pop();
+                        self.push(value);
+                        None
+                    }
+                }
+                Op::GetSuper => {
+                    let name = self.read_string();
+                    let super_class = self.pop().as_obj() as *mut ObjClass;
+                    self.bind_method(super_class, name)
+                }
                 Op::Equal => {
                     let b = self.pop();
                     let a = self.pop();
@@ -123,10 +333,24 @@ impl<'a> Runner<'a> {
                 }
                 Op::Greater => binary_op!(self, Bool, >),
                 Op::Less => binary_op!(self, Bool, <),
-                Op::Add => binary_op!(self, Number, +),
+                Op::Add => {
+                    if self.peek(0).is_string() && self.peek(1).is_string() {
+                        self.concatenate();
+                        None
+                    } else if let (Value::Number(_), Value::Number(_)) =
+                        (*self.peek(0), *self.peek(1))
+                    {
+                        binary_op!(self, Number, +)
+                    } else {
+                        self.runtime_error(
+                            "Operands must either both be numbers or both be strings.",
+                        )
+                    }
+                }
                 Op::Subtract => binary_op!(self, Number, -),
                 Op::Multiply => binary_op!(self, Number, *),
                 Op::Divide => binary_op!(self, Number, /),
+                Op::Modulo => binary_op!(self, Number, %),
                 Op::Negate => {
                     let value = self.peek(0);
                     match value {
@@ -142,32 +366,367 @@ impl<'a> Runner<'a> {
                     *value = Value::Bool(value.is_falsy());
                     None
                 }
+                Op::Print => {
+                    let value = self.pop();
+                    self.options.output.print(&value.to_string());
+                    None
+                }
+                Op::Jump => {
+                    let offset = self.read_short();
+                    self.jump(offset as isize);
+                    None
+                }
+                Op::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.peek(0).is_falsy() {
+                        self.jump(offset as isize);
+                    }
+                    None
+                }
+                Op::Loop => {
+                    let offset = self.read_short();
+                    self.jump(-(offset as isize));
+                    None
+                }
+                Op::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    let callee = *self.peek(arg_count);
+                    self.call_value(callee, arg_count)
+                }
+                Op::Invoke => {
+                    let method = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(method, arg_count)
+                }
+                Op::SuperInvoke => {
+                    let method = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    let super_class = self.pop().as_obj() as *mut ObjClass;
+                    self.invoke_from_class(super_class, method, arg_count)
+                }
+                Op::Closure => {
+                    let function = self.read_constant().as_obj() as *mut ObjFunction;
+                    let closure = self.new_closure(function);
+                    self.push(closure.into());
+
+                    for _ in 0..unsafe { (*function).upvalue_count } {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.upvalue(index)
+                        };
+                        unsafe { (*closure).upvalues.push(upvalue) };
+                    }
+                    None
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                    None
+                }
                 Op::Return => {
-                    self.pop().print();
-                    println!();
-                    Some(InterpretResult::Ok)
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    if self.frames.is_empty() {
+                        self.pop();
+                        Some(InterpretResult::Ok)
+                    } else {
+                        self.stack.truncate(frame.slots);
+                        self.push(result);
+                        None
+                    }
+                }
+                Op::Class => {
+                    let name = self.read_string();
+                    let class = self.new_class(name);
+                    self.push(class.into());
+                    None
+                }
+                Op::Inherit => {
+                    if !self.peek(1).is_class() {
+                        self.runtime_error("Super class must be a class")
+                    } else {
+                        let super_class = self.peek(1).as_obj() as *mut ObjClass;
+                        let sub_class = self.peek(0).as_obj() as *mut ObjClass;
+                        unsafe { (*sub_class).methods.add_all(&(*super_class).methods) };
+                        self.pop();
+                        None
+                    }
+                }
+                Op::Method => {
+                    let name = self.read_string();
+                    self.define_method(name);
+                    None
                 }
             };
 
             if let Some(result) = result {
-                if DEBUG_TRACE_EXECUTION {
-                    println!("!! End Execution !!")
+                if self.options.trace {
+                    writeln!(self.options.debug_output, "!! End Execution !!")
+                        .expect("Could not write trace.");
                 }
                 return result;
             }
         }
     }
 
+    /// Writes the stack and the next instruction to the debug output.
+    fn trace_instruction(&mut self) -> io::Result<()> {
+        let out = &mut self.options.debug_output;
+        write!(out, " ")?;
+        for value in self.stack.iter() {
+            write!(out, "[ {} ]", value)?;
+        }
+        writeln!(out)?;
+
+        let frame = unsafe { self.frames.last().unwrap_unchecked() };
+        let chunk = unsafe { &(*(*frame.closure).function).chunk };
+        chunk.disassemble_instruction(out, frame.ip)?;
+        Ok(())
+    }
+
+    fn concatenate(&mut self) {
+        let b = unsafe { &*self.peek(0).as_string() };
+        let a = unsafe { &*self.peek(1).as_string() };
+
+        let mut chars = String::with_capacity(a.chars.len() + b.chars.len());
+        chars.push_str(&a.chars);
+        chars.push_str(&b.chars);
+        let result = self.take_string(chars);
+
+        self.pop();
+        self.pop();
+        self.push(result.into());
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Option<InterpretResult> {
+        if let Value::Obj(obj) = callee {
+            match unsafe { (*obj).obj_type } {
+                ObjType::BoundMethod => {
+                    let bound_method = unsafe { (*obj).as_bound_method() };
+                    // The receiver takes the slot of the callee, where `this`
+                    // expects it.
+                    *self.peek(arg_count) = bound_method.receiver;
+                    return self.call(bound_method.method, arg_count);
+                }
+                ObjType::Class => {
+                    let class = obj as *mut ObjClass;
+                    let instance = self.new_instance(class);
+                    *self.peek(arg_count) = instance.into();
+
+                    let init = self.init_string;
+                    return match unsafe { (*class).methods.get(init) } {
+                        Some(initializer) => {
+                            self.call(initializer.as_obj() as *mut ObjClosure, arg_count)
+                        }
+                        None if arg_count != 0 => self
+                            .runtime_error(&format!("Expected 0 arguments but got {}.", arg_count)),
+                        None => None,
+                    };
+                }
+                ObjType::Closure => return self.call(obj as *mut ObjClosure, arg_count),
+                ObjType::Native => {
+                    let native = unsafe { (*obj).as_native() };
+                    if arg_count != native.arity {
+                        return self.runtime_error(&format!(
+                            "Expected {} arguments but got {}.",
+                            native.arity, arg_count
+                        ));
+                    }
+
+                    let args_start = self.stack.len() - arg_count;
+                    // The arguments stay on the stack while the native runs, so
+                    // that they survive collections triggered by it.
+                    let args = self.stack[args_start..].to_vec();
+                    match (native.function)(self, &args) {
+                        Ok(result) => {
+                            // Pop the arguments and the native function.
+                            self.stack.truncate(args_start - 1);
+                            self.push(result);
+                            return None;
+                        }
+                        Err(message) => return self.runtime_error(&message),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.runtime_error("Can only call functions and classes.")
+    }
+
+    fn invoke(&mut self, name: *mut ObjString, arg_count: usize) -> Option<InterpretResult> {
+        let receiver = *self.peek(arg_count);
+        if !receiver.is_instance() {
+            return self.runtime_error("Only instances have properties.");
+        }
+
+        let instance = receiver.as_obj() as *mut ObjInstance;
+        // Fields shadow methods and may hold any callable value.
+        if let Some(value) = unsafe { (*instance).fields.get(name) } {
+            *self.peek(arg_count) = value;
+            return self.call_value(value, arg_count);
+        }
+
+        self.invoke_from_class(unsafe { (*instance).class }, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: *mut ObjClass,
+        name: *mut ObjString,
+        arg_count: usize,
+    ) -> Option<InterpretResult> {
+        match unsafe { (*class).methods.get(name) } {
+            Some(method) => self.call(method.as_obj() as *mut ObjClosure, arg_count),
+            None => self.runtime_error(&format!("Undefined property '{}'.", unsafe {
+                &(*name).chars
+            })),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name`, bound
+    /// to the instance.
+    fn bind_method(
+        &mut self,
+        class: *mut ObjClass,
+        name: *mut ObjString,
+    ) -> Option<InterpretResult> {
+        let method = match unsafe { (*class).methods.get(name) } {
+            Some(method) => method.as_obj() as *mut ObjClosure,
+            None => {
+                return self.runtime_error(&format!("Undefined property '{}'.", unsafe {
+                    &(*name).chars
+                }))
+            }
+        };
+
+        let receiver = *self.peek(0);
+        let bound_method = self.new_bound_method(receiver, method);
+        self.pop();
+        self.push(bound_method.into());
+        None
+    }
+
+    /// Adds the closure on top of the stack as method `name` to the class
+    /// below it.
+    fn define_method(&mut self, name: *mut ObjString) {
+        let method = *self.peek(0);
+        let class = self.peek(1).as_obj() as *mut ObjClass;
+        unsafe { (*class).methods.set(name, method) };
+        self.pop();
+    }
+
+    fn call(&mut self, closure: *mut ObjClosure, arg_count: usize) -> Option<InterpretResult> {
+        let arity = unsafe { (*(*closure).function).arity };
+        if arg_count != arity {
+            return self.runtime_error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            ));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return self.runtime_error("Stack overflow.");
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            // The callee itself occupies the first slot.
+            slots: self.stack.len() - arg_count - 1,
+        });
+        None
+    }
+
+    /// Returns the open upvalue for the given stack slot, creating it if
+    /// necessary.
+    fn capture_upvalue(&mut self, location: usize) -> *mut ObjUpvalue {
+        let mut prev_upvalue: *mut ObjUpvalue = ptr::null_mut();
+        let mut upvalue = self.open_upvalues;
+        while !upvalue.is_null() && unsafe { (*upvalue).location } > location {
+            prev_upvalue = upvalue;
+            upvalue = unsafe { (*upvalue).next };
+        }
+
+        if !upvalue.is_null() && unsafe { (*upvalue).location } == location {
+            return upvalue;
+        }
+
+        let created_upvalue = self.new_upvalue(location);
+        unsafe {
+            (*created_upvalue).next = upvalue;
+            if prev_upvalue.is_null() {
+                self.open_upvalues = created_upvalue;
+            } else {
+                (*prev_upvalue).next = created_upvalue;
+            }
+        }
+
+        created_upvalue
+    }
+
+    /// Moves the values of all open upvalues at or above `last` in the stack
+    /// into the upvalues themselves.
+    fn close_upvalues(&mut self, last: usize) {
+        while !self.open_upvalues.is_null() && unsafe { (*self.open_upvalues).location } >= last {
+            let upvalue = unsafe { &mut *self.open_upvalues };
+            upvalue.closed = Some(self.stack[upvalue.location]);
+            self.open_upvalues = upvalue.next;
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        unsafe { self.frames.last().unwrap_unchecked() }
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        unsafe { self.frames.last_mut().unwrap_unchecked() }
+    }
+
+    fn upvalue(&self, slot: usize) -> *mut ObjUpvalue {
+        unsafe { (&(*self.frame().closure).upvalues)[slot] }
+    }
+
+    fn function(&self) -> &ObjFunction {
+        unsafe { &*(*self.frame().closure).function }
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.function().chunk
+    }
+
     fn read_byte(&mut self) -> u8 {
-        unsafe { *self.ip.next().unwrap_unchecked() }
+        let frame = self.frame_mut();
+        let byte = unsafe {
+            *(*(*frame.closure).function)
+                .chunk
+                .code()
+                .get_unchecked(frame.ip)
+        };
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+
+    fn jump(&mut self, offset: isize) {
+        let frame = self.frame_mut();
+        frame.ip = (frame.ip as isize + offset) as usize;
     }
 
     fn read_constant(&mut self) -> Value {
-        self.chunk.constants()[self.read_byte() as usize]
+        let index = self.read_byte() as usize;
+        self.chunk().constants()[index]
     }
 
-    fn instruction_offset(&self) -> usize {
-        self.chunk.code().len() - self.ip.as_slice().len()
+    fn read_string(&mut self) -> *mut ObjString {
+        self.read_constant().as_string()
     }
 
     fn peek(&mut self, index: usize) -> &mut Value {
@@ -177,20 +736,35 @@ impl<'a> Runner<'a> {
         }
     }
 
-    fn push(&mut self, value: Value) {
+    pub fn push(&mut self, value: Value) {
         self.stack.push(value)
     }
 
-    fn pop(&mut self) -> Value {
+    pub fn pop(&mut self) -> Value {
         unsafe { self.stack.pop().unwrap_unchecked() }
     }
 
     fn runtime_error(&mut self, message: &str) -> Option<InterpretResult> {
-        eprintln!("{}", message);
+        let function = self.function();
+        let span = function.chunk.spans()[self.frame().ip - 1];
+        let mut error = format!("{}\n{}", message, span.snippet(function.chunk.source()));
 
-        let instruction = self.instruction_offset() - 1;
-        let line = self.chunk.lines()[instruction];
-        eprintln!("[line {}] in script", line);
+        // Report the calls in progress, starting with the innermost one.
+        for frame in self.frames.iter().rev() {
+            let function = unsafe { &*(*frame.closure).function };
+            let line = function.chunk.spans()[frame.ip - 1].line;
+            let location = match (function.name.is_null(), function.class_name.is_null()) {
+                (true, _) => "script".to_string(),
+                (false, true) => format!("{}()", unsafe { &(*function.name).chars }),
+                (false, false) => format!(
+                    "{}.{}()",
+                    unsafe { &(*function.class_name).chars },
+                    unsafe { &(*function.name).chars }
+                ),
+            };
+            error.push_str(&format!("\n[line {}] in {}", line, location));
+        }
+        self.options.output.error(&error);
 
         self.reset_stack();
 
@@ -199,5 +773,7 @@ impl<'a> Runner<'a> {
 
     fn reset_stack(&mut self) {
         self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues = ptr::null_mut();
     }
 }
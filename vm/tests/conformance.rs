@@ -0,0 +1,10 @@
+#[path = "../../test/harness.rs"]
+mod harness;
+
+#[test]
+fn conformance() {
+    harness::run_suite(
+        env!("CARGO_BIN_EXE_vm"),
+        &["exception", "import", "list", "map", "string_library"],
+    );
+}